@@ -0,0 +1,67 @@
+//! Time aggregates of random ranges with each range structure: `cargo bench --bench range_aggregate`
+#![allow(dead_code, unused_macros)]
+
+#[path = "../src/monoid.rs"]
+mod monoid;
+#[path = "../src/tree/mod.rs"]
+mod tree;
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use monoid::Max;
+use tree::{ GenericTree, RangeAggregate };
+use tree::segment_tree::SegmentTree;
+use tree::fenwick_tree::FenwickTree;
+use tree::sparse_table::SparseTable;
+
+const LOOKUPS: usize = 1_000_000;
+
+/// Pseudo-random numbers (xorshift), so that runs aggregate the same ranges
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+/// Print the time spent per range and the storage of a structure
+fn time<T: Copy, R: RangeAggregate<T>>(name: &str, structure: &R, ranges: &[(usize, usize)], bytes: usize) {
+    let timer = Instant::now();
+    for &(left, right) in ranges {
+        black_box(structure.aggregate(left, right));
+    }
+    let elapsed = timer.elapsed();
+    println!("    {:<14} {:>7.1} ns per range, {:>6} MiB",
+             name, elapsed.as_nanos() as f64 / ranges.len() as f64, bytes >> 20);
+}
+
+fn bench(leaves: usize) {
+    let mut random = XorShift(0x2545_f491_4f6c_dd1d);
+    let counts: Vec<usize> = (0 .. leaves).map(|_| random.next(100) as usize).collect();
+    let ranges: Vec<(usize, usize)> = (0 .. LOOKUPS).map(|_| {
+                                                        let left = random.next(leaves as u64) as usize;
+                                                        (left, left + random.next((leaves - left) as u64) as usize)
+                                                    })
+                                                    .collect();
+    println!("{} leaves", leaves);
+
+    let segment_tree: SegmentTree<usize> = SegmentTree::with_leaves(&counts);
+    time("segment tree", &segment_tree, &ranges, 2 * leaves.next_power_of_two() * 8);
+    time("fenwick tree", &FenwickTree::with_leaves(&counts), &ranges, (leaves + 1) * 8);
+
+    let maxima: Vec<Max<usize>> = counts.iter().map(|&count| Max(Some(count))).collect();
+    let segment_tree: SegmentTree<Max<usize>> = SegmentTree::with_leaves(&maxima);
+    time("segment tree", &segment_tree, &ranges, 2 * leaves.next_power_of_two() * 16);
+    time("sparse table", &SparseTable::with_leaves(&maxima), &ranges, (leaves.ilog2() as usize + 1) * leaves * 16);
+}
+
+fn main() {
+    for &leaves in &[1_000, 20_000, 1_000_000] {
+        bench(leaves);
+    }
+}
@@ -0,0 +1,63 @@
+//! Time lookups of ranges of dates in the range tree: `cargo bench --bench range_tree`
+#![allow(dead_code, unused_macros)]
+
+extern crate chrono;
+
+#[path = "../src/monoid.rs"]
+mod monoid;
+#[path = "../src/tree/mod.rs"]
+mod tree;
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use chrono::{ Duration, NaiveDate, NaiveDateTime };
+
+use tree::GenericTree;
+use tree::range_tree::RangeTree;
+
+const LOOKUPS: usize = 1_000_000;
+
+/// Pseudo-random numbers (xorshift), so that runs look up the same ranges
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+fn bench(dates: usize) {
+    let start = NaiveDate::from_ymd_opt(2015, 8, 1).and_then(|date| date.and_hms_opt(0, 0, 0)).unwrap();
+    let leaves: Vec<NaiveDateTime> = (0 .. dates as i64).map(|second| start + Duration::seconds(2 * second))
+                                                        .collect();
+    let tree = RangeTree::with_leaves(&leaves);
+
+    let mut random = XorShift(0x2545_f491_4f6c_dd1d);
+    let ranges: Vec<(NaiveDateTime, NaiveDateTime)> = (0 .. LOOKUPS).map(|_| {
+                                                                         let from = random.next(2 * dates as u64) as i64;
+                                                                         let to = from + random.next(2 * dates as u64) as i64;
+                                                                         (start + Duration::seconds(from), start + Duration::seconds(to))
+                                                                     })
+                                                                     .collect();
+
+    let timer = Instant::now();
+    let mut found = 0;
+    for &(from, to) in &ranges {
+        if let Some((from_id, to_id)) = black_box(tree.largest_range(&from, &to)) {
+            found += to_id - from_id;
+        }
+    }
+    let elapsed = timer.elapsed();
+    println!("{:>9} dates: {:>7.1} ns per range ({})",
+             dates, elapsed.as_nanos() as f64 / LOOKUPS as f64, black_box(found));
+}
+
+fn main() {
+    for &dates in &[1_000, 20_000, 1_000_000, 10_000_000] {
+        bench(dates);
+    }
+}
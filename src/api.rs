@@ -0,0 +1,70 @@
+use chrono::NaiveDateTime;
+
+use problem::Problem;
+
+/// Lifecycle of an API version
+#[derive(Clone, Copy, PartialEq)]
+pub enum Status {
+    /// Served
+    Current,
+    /// Served, responses carry a `Deprecation` header
+    Deprecated,
+    /// Not served anymore, requests are answered with 410
+    Retired
+}
+
+/// How handlers shape their responses for an API version
+pub struct Api {
+    pub version: u32,
+    pub status: Status,
+    /// Format dates of responses
+    pub format_date: fn(&NaiveDateTime) -> String,
+    /// Sort popular queries by decreasing count
+    pub sort_popular: bool,
+    /// Give the path of the failed request in problem details
+    pub problem_instance: bool
+}
+
+/// Registry of API versions, responses of a version must never change once it is published
+static VERSIONS: [Api; 2] = [
+    Api {
+        version: 1,
+        status: Status::Deprecated,
+        format_date: format_date_v1,
+        sort_popular: false,
+        problem_instance: false
+    },
+    Api {
+        version: 2,
+        status: Status::Current,
+        format_date: format_date_iso,
+        sort_popular: true,
+        problem_instance: true
+    }
+];
+
+fn format_date_v1(date: &NaiveDateTime) -> String {
+    date.to_string()
+}
+
+fn format_date_iso(date: &NaiveDateTime) -> String {
+    date.format("%Y-%m-%dT%H:%M:%S").to_string()
+}
+
+/// Find the API version a URI starts with and return it with the rest of the URI. URIs whose
+/// first segment is not a number are not versioned.
+pub fn resolve(uri: &str) -> Result<(Option<&'static Api>, &str), Problem> {
+    let segment_end = uri.char_indices()
+                         .skip(1)
+                         .find(|&(_, c)| c == '/' || c == '?')
+                         .map_or(uri.len(), |(index, _)| index);
+    let maybe_version = uri.get(1 .. segment_end)
+                           .and_then(|segment| segment.parse::<u32>().ok());
+    match maybe_version {
+        None => Ok((None, uri)),
+        Some(version) => VERSIONS.iter()
+                                 .find(|api| api.version == version)
+                                 .map(|api| (Some(api), &uri[segment_end..]))
+                                 .ok_or_else(|| Problem::unsupported_version(version))
+    }
+}
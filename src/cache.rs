@@ -0,0 +1,98 @@
+use std::collections::{ BTreeMap, HashMap };
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use chrono::NaiveDateTime;
+
+use filter::QueryFilter;
+
+type Date = NaiveDateTime;
+
+/// Least recently used cache, holding at most `capacity` entries
+pub struct Lru<K, V> {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<K, (V, u64)>,          // Values and the tick of their last use
+    uses: BTreeMap<u64, K>                  // Keys ordered by their last use
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Lru<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Lru {
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+            uses: BTreeMap::new()
+        }
+    }
+
+    /// Find a value and mark it as the most recently used, in O(log N)
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.entries.get_mut(key)?;
+        self.uses.remove(&entry.1);
+        self.uses.insert(tick, key.clone());
+        entry.1 = tick;
+        Some(entry.0.clone())
+    }
+
+    /// Insert a value, evicting the least recently used one if the cache is full, in O(log N)
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        if let Some((_, tick)) = self.entries.remove(&key) {
+            self.uses.remove(&tick);
+        } else if self.entries.len() == self.capacity {
+            if let Some((_, oldest)) = self.uses.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.uses.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+    }
+}
+
+/// Number of queries, or of distinct queries, in a range of an index, optionally only counting
+/// the ones matching a filter
+type CountKey = (u64, Date, Date, bool, Option<QueryFilter>);
+
+/// K most frequent queries in a range of an index, optionally among the ones matching a filter
+type PopularKey = (u64, Date, Date, usize, Option<QueryFilter>);
+
+/// Results of solvers, keyed by the generation of the index they were computed from so that
+/// entries of a previous index are never served and age out
+pub struct ResultCache {
+    counts: Mutex<Lru<CountKey, usize>>,
+    popular: Mutex<Lru<PopularKey, Vec<(String, usize)>>>
+}
+
+impl ResultCache {
+    pub fn new(capacity: usize) -> Self {
+        ResultCache {
+            counts: Mutex::new(Lru::new(capacity)),
+            popular: Mutex::new(Lru::new(capacity))
+        }
+    }
+
+    pub fn count<E, F: FnOnce() -> Result<usize, E>>(&self, key: CountKey, compute: F) -> Result<usize, E> {
+        cached(&self.counts, key, compute)
+    }
+
+    pub fn popular<E, F: FnOnce() -> Result<Vec<(String, usize)>, E>>(&self, key: PopularKey, compute: F) -> Result<Vec<(String, usize)>, E> {
+        cached(&self.popular, key, compute)
+    }
+}
+
+/// Look a result up, computing it without holding the lock when it is missing. Failures are not
+/// cached.
+fn cached<K: Hash + Eq + Clone, V: Clone, E, F: FnOnce() -> Result<V, E>>(lru: &Mutex<Lru<K, V>>, key: K, compute: F) -> Result<V, E> {
+    if let Some(value) = lru.lock().unwrap().get(&key) {
+        return Ok(value);
+    }
+    let value = compute()?;
+    lru.lock().unwrap().insert(key, value.clone());
+    Ok(value)
+}
@@ -0,0 +1,92 @@
+use std::env;
+use std::str::FromStr;
+use std::thread;
+
+use logger::{ Level, LogFormat };
+use solver::{ SolverOptions, RangeIndex };
+use normalize::Normalization;
+
+/// Configuration of the service, read from the environment
+pub struct Config {
+    /// Log file of queries the solver is built from, reloaded on SIGHUP (`ALGOLIA_DATA_FILE`)
+    pub data_file: String,
+    /// Messages less severe than this are not logged (`ALGOLIA_LOG_LEVEL`)
+    pub log_level: Level,
+    /// Format of log lines (`ALGOLIA_LOG_FORMAT`)
+    pub log_format: LogFormat,
+    /// File logs are appended to instead of the standard output (`ALGOLIA_LOG_FILE`)
+    pub log_file: Option<String>,
+    /// The log file is rotated once it reaches this size (`ALGOLIA_LOG_MAX_BYTES`)
+    pub log_max_bytes: u64,
+    /// Number of rotated log files kept besides the current one (`ALGOLIA_LOG_KEEP`)
+    pub log_keep: usize,
+    /// Number of results of each kind kept in the result cache (`ALGOLIA_CACHE_ENTRIES`)
+    pub cache_entries: usize,
+    /// Seconds during which clients may reuse a response without revalidating it (`ALGOLIA_CACHE_MAX_AGE`)
+    pub cache_max_age: u64,
+    /// Milliseconds after which a request is abandoned, 0 for no limit (`ALGOLIA_REQUEST_TIMEOUT_MS`)
+    pub request_timeout_ms: u64,
+    /// Largest number of queries a request may ask for (`ALGOLIA_MAX_SIZE`)
+    pub max_size: usize,
+    /// Largest number of occurrences a request may scan, 0 for no limit (`ALGOLIA_MAX_COST`)
+    pub max_cost: usize,
+    /// Number of threads running scans of the index (`ALGOLIA_WORKERS`)
+    pub workers: usize,
+    /// Number of scans waiting for a worker before new ones are rejected (`ALGOLIA_WORKER_QUEUE`)
+    pub worker_queue: usize,
+    /// Number of threads a scan of the index is split between (`ALGOLIA_SCAN_THREADS`)
+    pub scan_threads: usize,
+    /// Scans of fewer occurrences run on a single thread (`ALGOLIA_SCAN_THRESHOLD`)
+    pub scan_threshold: usize,
+    /// Number of threads parsing the log (`ALGOLIA_LOAD_THREADS`)
+    pub load_threads: usize,
+    /// Structures aggregating ranges of dates, `segment` or `fenwick-sparse`
+    /// (`ALGOLIA_RANGE_INDEX`)
+    pub range_index: RangeIndex,
+    /// Steps normalizing queries before they are indexed, none by default (`ALGOLIA_NORMALIZE`)
+    pub normalization: Normalization
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, String> {
+        let cpus = thread::available_parallelism().map_or(4, |cpus| cpus.get());
+        Ok(Config {
+            data_file: env::var("ALGOLIA_DATA_FILE").unwrap_or_else(|_| "hn_logs.tsv".to_string()),
+            log_level: var("ALGOLIA_LOG_LEVEL", Level::Info)?,
+            log_format: var("ALGOLIA_LOG_FORMAT", LogFormat::Json)?,
+            log_file: env::var("ALGOLIA_LOG_FILE").ok(),
+            log_max_bytes: var("ALGOLIA_LOG_MAX_BYTES", 10 << 20)?,
+            log_keep: var("ALGOLIA_LOG_KEEP", 5)?,
+            cache_entries: var("ALGOLIA_CACHE_ENTRIES", 1024)?,
+            cache_max_age: var("ALGOLIA_CACHE_MAX_AGE", 60)?,
+            request_timeout_ms: var("ALGOLIA_REQUEST_TIMEOUT_MS", 10000)?,
+            max_size: var("ALGOLIA_MAX_SIZE", 1000)?,
+            max_cost: var("ALGOLIA_MAX_COST", 0)?,
+            workers: var("ALGOLIA_WORKERS", cpus)?,
+            worker_queue: var("ALGOLIA_WORKER_QUEUE", 64)?,
+            scan_threads: var("ALGOLIA_SCAN_THREADS", cpus)?,
+            scan_threshold: var("ALGOLIA_SCAN_THRESHOLD", 100000)?,
+            load_threads: var("ALGOLIA_LOAD_THREADS", cpus)?,
+            range_index: var("ALGOLIA_RANGE_INDEX", RangeIndex::Segment)?,
+            normalization: var("ALGOLIA_NORMALIZE", Normalization::default())?
+        })
+    }
+
+    pub fn solver_options(&self) -> SolverOptions {
+        SolverOptions {
+            scan_threads: self.scan_threads,
+            scan_threshold: self.scan_threshold,
+            load_threads: self.load_threads,
+            range_index: self.range_index,
+            normalization: self.normalization
+        }
+    }
+}
+
+/// Parse an environment variable, `default` is used when it is not set
+fn var<T: FromStr>(name: &str, default: T) -> Result<T, String> {
+    match env::var(name) {
+        Ok(value) => T::from_str(&value).map_err(|_| format!("Invalid value '{}' for {}", value, name)),
+        Err(_) => Ok(default)
+    }
+}
@@ -0,0 +1,30 @@
+use std::time::{ Duration, Instant };
+
+/// Point in time after which a computation is abandoned
+#[derive(Clone, Copy)]
+pub struct Deadline {
+    at: Option<Instant>
+}
+
+/// The deadline of a computation passed before it completed
+#[derive(Debug)]
+pub struct Expired;
+
+impl Deadline {
+    /// Deadline `timeout` from now, there is none when `timeout` is zero
+    pub fn after(timeout: Duration) -> Self {
+        Deadline {
+            at: match timeout.as_nanos() {
+                0 => None,
+                _ => Some(Instant::now() + timeout)
+            }
+        }
+    }
+
+    pub fn check(&self) -> Result<(), Expired> {
+        match self.at {
+            Some(at) if Instant::now() >= at => Err(Expired),
+            _ => Ok(())
+        }
+    }
+}
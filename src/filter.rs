@@ -0,0 +1,62 @@
+use std::hash::{ Hash, Hasher };
+
+use regex::{ self, Regex, RegexBuilder };
+
+/// Selection of queries by their text: queries containing a string, ignoring case or not, or
+/// matching a regular expression
+#[derive(Clone, Debug)]
+pub struct QueryFilter {
+    pattern: String,                // String or regular expression, as given
+    ignore_case: bool,              // Whether letters match regardless of their case
+    regex: bool,                    // Whether the pattern is a regular expression
+    matcher: Option<Regex>          // Compiled pattern, unless it is searched as it is
+}
+
+impl QueryFilter {
+    /// Compile a filter. Case-insensitive filters are compiled into regular expressions, so that
+    /// every case of a letter matches and not only the lower case one.
+    pub fn new(pattern: &str, ignore_case: bool, regex: bool) -> Result<Self, regex::Error> {
+        let matcher = match (ignore_case, regex) {
+            (false, false) => None,
+            (ignore_case, regex) => {
+                let expression = if regex { pattern.to_string() } else { regex::escape(pattern) };
+                Some(RegexBuilder::new(&expression).case_insensitive(ignore_case)
+                                                   .build()?)
+            }
+        };
+        Ok(QueryFilter {
+            pattern: pattern.to_string(),
+            ignore_case,
+            regex,
+            matcher
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn matches(&self, query: &str) -> bool {
+        match self.matcher {
+            Some(ref matcher) => matcher.is_match(query),
+            None => query.contains(self.pattern.as_str())
+        }
+    }
+}
+
+/// Filters are identified by their definition, the compiled pattern follows from it
+impl PartialEq for QueryFilter {
+    fn eq(&self, other: &Self) -> bool {
+        (&self.pattern, self.ignore_case, self.regex) == (&other.pattern, other.ignore_case, other.regex)
+    }
+}
+
+impl Eq for QueryFilter {}
+
+impl Hash for QueryFilter {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.pattern.hash(state);
+        self.ignore_case.hash(state);
+        self.regex.hash(state);
+    }
+}
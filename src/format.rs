@@ -0,0 +1,301 @@
+use std::str::FromStr;
+
+use hyper::StatusCode;
+
+use serde_json;
+
+use problem::Problem;
+
+/// Representations of a response
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Csv,
+    Tsv,
+    Ndjson
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Csv => "text/csv",
+            Format::Tsv => "text/tab-separated-values",
+            Format::Ndjson => "application/x-ndjson"
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Csv => "csv",
+            Format::Tsv => "tsv",
+            Format::Ndjson => "ndjson"
+        }
+    }
+
+    /// Find the format of a media range of an `Accept` header
+    fn from_media_range(media_range: &str) -> Option<Self> {
+        match media_range {
+            "*/*" | "application/*" | "application/json" => Some(Format::Json),
+            "text/*" | "text/csv" => Some(Format::Csv),
+            "text/tab-separated-values" => Some(Format::Tsv),
+            "application/x-ndjson" | "application/ndjson" => Some(Format::Ndjson),
+            _ => None
+        }
+    }
+
+    /// Choose the format of a response among the supported ones. An explicit `format` parameter
+    /// takes precedence over the `Accept` header, JSON is used when none is given.
+    pub fn negotiate(format: Option<Format>, accept: Option<&str>, supported: &[Format]) -> Result<Self, Problem> {
+        if let Some(format) = format {
+            if supported.contains(&format) {
+                return Ok(format);
+            }
+            return Err(not_acceptable(supported));
+        }
+
+        let accept = match accept {
+            Some(accept) => accept,
+            None => return Ok(Format::Json)
+        };
+
+        // Rank media ranges by their quality value, the sort is stable so that the order of the
+        // header breaks ties
+        let mut media_ranges: Vec<(&str, f32)> = accept.split(',')
+                                                       .map(|media_range| {
+                                                           let mut parts = media_range.split(';').map(str::trim);
+                                                           let name = parts.next().unwrap_or_default();
+                                                           let quality = parts.filter_map(|part| part.strip_prefix("q="))
+                                                                              .filter_map(|q| q.parse::<f32>().ok())
+                                                                              .next()
+                                                                              .unwrap_or(1.0);
+                                                           (name, quality)
+                                                       })
+                                                       .filter(|&(_, quality)| quality > 0.0)
+                                                       .collect();
+        media_ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(::std::cmp::Ordering::Equal));
+
+        media_ranges.iter()
+                    .filter_map(|&(name, _)| Format::from_media_range(name))
+                    .find(|format| supported.contains(format))
+                    .ok_or_else(|| not_acceptable(supported))
+    }
+}
+
+fn not_acceptable(supported: &[Format]) -> Problem {
+    let names: Vec<&str> = supported.iter().map(|format| format.name()).collect();
+    Problem::new(StatusCode::NOT_ACCEPTABLE, format!("Available formats are {}", names.join(", ")))
+}
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        [Format::Json, Format::Csv, Format::Tsv, Format::Ndjson].iter()
+                                                                .cloned()
+                                                                .find(|format| format.name() == data)
+                                                                .ok_or(())
+    }
+}
+
+/// Records of an output, produced lazily while the response is rendered
+pub type Records = Box<dyn Iterator<Item=serde_json::Value> + Send>;
+
+/// Outputs with more records than this (or an unknown number of them) are streamed
+const STREAMING_RECORDS: usize = 1000;
+
+/// Streamed bodies are sent in chunks of about this size
+const CHUNK_BYTES: usize = 1 << 16;
+
+/// Where the records are placed in the JSON document of an output
+enum Layout {
+    /// The document is the only record
+    Single,
+    /// The document is the array of records
+    Array,
+    /// The records are listed under a field of the document
+    Field(serde_json::Map<String, serde_json::Value>, &'static str)
+}
+
+/// Result of a handler before being rendered in a format
+pub struct Output {
+    layout: Layout,
+    /// Records rendered one per line as NDJSON and CSV
+    records: Records,
+    /// Fields of the records given as CSV and TSV columns, there is no CSV nor TSV representation
+    /// without columns
+    columns: &'static [&'static str]
+}
+
+/// Body of a response, large ones are streamed in chunks as they are rendered
+pub enum Content {
+    Full(String),
+    Stream(Box<dyn Iterator<Item=String> + Send>)
+}
+
+impl Output {
+    /// An output made of a single record
+    pub fn single(record: serde_json::Value, columns: &'static [&'static str]) -> Self {
+        Output {
+            layout: Layout::Single,
+            records: Box::new(::std::iter::once(record)),
+            columns
+        }
+    }
+
+    /// An output whose JSON document is the array of its records
+    pub fn array(records: Records, columns: &'static [&'static str]) -> Self {
+        Output {
+            layout: Layout::Array,
+            records,
+            columns
+        }
+    }
+
+    /// An output whose records are listed under `field` of the JSON object `document`
+    pub fn field(document: serde_json::Value, field: &'static str, records: Records,
+                 columns: &'static [&'static str]) -> Self {
+        let object = match document {
+            serde_json::Value::Object(object) => object,
+            _ => serde_json::Map::new()
+        };
+        Output {
+            layout: Layout::Field(object, field),
+            records,
+            columns
+        }
+    }
+
+    /// Build the JSON document of the output
+    pub fn to_json(mut self) -> serde_json::Value {
+        match self.layout {
+            Layout::Single => self.records.next().unwrap_or(serde_json::Value::Null),
+            Layout::Array => serde_json::Value::Array(self.records.collect()),
+            Layout::Field(mut object, field) => {
+                object.insert(field.to_string(), serde_json::Value::Array(self.records.collect()));
+                serde_json::Value::Object(object)
+            }
+        }
+    }
+
+    pub fn supported_formats(&self) -> &'static [Format] {
+        match self.columns.len() {
+            0 => &[Format::Json, Format::Ndjson],
+            _ => &[Format::Json, Format::Csv, Format::Tsv, Format::Ndjson]
+        }
+    }
+
+    pub fn render(self, format: Format) -> Content {
+        let streamed = match self.records.size_hint() {
+            (_, Some(upper)) => upper > STREAMING_RECORDS,
+            (_, None) => true
+        };
+
+        let pieces = self.pieces(format);
+        if streamed {
+            Content::Stream(Box::new(Chunks { pieces }))
+        } else {
+            Content::Full(pieces.collect())
+        }
+    }
+
+    /// Render the output as a sequence of strings, each record being rendered when the
+    /// sequence reaches it
+    fn pieces(self, format: Format) -> Box<dyn Iterator<Item=String> + Send> {
+        let columns = self.columns;
+        match format {
+            Format::Ndjson => Box::new(self.records.map(|record| format!("{}\n", record))),
+
+            Format::Csv => {
+                let header = csv_line(columns.iter().map(|column| column.to_string()));
+                let lines = self.records.map(move |record| csv_line(columns.iter().map(|&column| csv_field(&record[column]))));
+                Box::new(::std::iter::once(header).chain(lines))
+            },
+
+            // Like the log file: no header and no quoting
+            Format::Tsv => Box::new(self.records.map(move |record| {
+                let fields: Vec<String> = columns.iter()
+                                                 .map(|&column| csv_field(&record[column]).replace(&['\t', '\n', '\r'][..], " "))
+                                                 .collect();
+                format!("{}\n", fields.join("\t"))
+            })),
+
+            Format::Json => match self.layout {
+                Layout::Single => Box::new(self.records.map(|record| record.to_string())),
+
+                Layout::Array => Box::new(::std::iter::once("[".to_string()).chain(json_list(self.records))
+                                                                            .chain(::std::iter::once("]".to_string()))),
+
+                // Fields are serialized in the same order as serde_json does, the records being
+                // inserted at the place of their field
+                Layout::Field(object, field) => {
+                    let json_field = |(key, value): (&String, &serde_json::Value)| format!("{}:{}", json!(key), value);
+                    let mut before: Vec<String> = object.iter()
+                                                        .filter(|&(key, _)| key.as_str() < field)
+                                                        .map(json_field)
+                                                        .collect();
+                    before.push(format!("{}:[", json!(field)));
+                    let after: Vec<String> = object.iter()
+                                                   .filter(|&(key, _)| key.as_str() > field)
+                                                   .map(json_field)
+                                                   .collect();
+                    let after = match after.len() {
+                        0 => "]}".to_string(),
+                        _ => format!("],{}}}", after.join(","))
+                    };
+                    Box::new(::std::iter::once(format!("{{{}", before.join(","))).chain(json_list(self.records))
+                                                                               .chain(::std::iter::once(after)))
+                }
+            }
+        }
+    }
+}
+
+/// Serialize records separated by commas
+fn json_list(records: Records) -> impl Iterator<Item=String> + Send {
+    records.enumerate().map(|(index, record)| match index {
+        0 => record.to_string(),
+        _ => format!(",{}", record)
+    })
+}
+
+/// Concatenate rendered pieces into chunks of about `CHUNK_BYTES`
+struct Chunks {
+    pieces: Box<dyn Iterator<Item=String> + Send>
+}
+
+impl Iterator for Chunks {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let mut chunk = self.pieces.next()?;
+        while chunk.len() < CHUNK_BYTES {
+            match self.pieces.next() {
+                Some(piece) => chunk.push_str(&piece),
+                None => break
+            }
+        }
+        Some(chunk)
+    }
+}
+
+fn csv_field(value: &serde_json::Value) -> String {
+    match *value {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(ref string) => string.clone(),
+        ref value => value.to_string()
+    }
+}
+
+/// Join fields of a CSV line, fields are quoted when needed (RFC 4180)
+fn csv_line<I: Iterator<Item=String>>(fields: I) -> String {
+    let fields: Vec<String> = fields.map(|field| {
+        if field.contains(&[',', '"', '\n', '\r'][..]) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field
+        }
+    }).collect();
+    format!("{}\r\n", fields.join(","))
+}
@@ -0,0 +1,237 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::hash_map::{ DefaultHasher, HashMap };
+use std::hash::{ Hash, Hasher };
+use std::io::{ self, Read };
+use std::str;
+use std::sync::Mutex;
+use std::sync::mpsc::{ self, Receiver };
+use std::thread;
+
+use chrono::{ NaiveDate, NaiveDateTime };
+
+use normalize::Normalization;
+use state::Progress;
+
+type Date = NaiveDateTime;
+type QueryId = u64;
+
+/// Size of the blocks of the log handed to parsing workers
+const BLOCK_BYTES: usize = 1 << 20;
+
+/// Rows of a log, parsed into sorted runs of (date, query) entries
+pub struct Parsed {
+    pub queries: HashMap<QueryId, String>,  // Storage of canonical queries
+    pub variants: Variants,                 // Raw queries of each canonical one, if normalized
+    pub runs: Vec<Vec<(Date, QueryId)>>,    // Entries parsed by each worker, sorted
+    pub rejected_rows: usize                // Rows that could not be parsed and were skipped
+}
+
+/// Raw queries mapped to each canonical query along with their number of rows
+pub type Variants = HashMap<QueryId, HashMap<String, usize>>;
+
+/// Identifier of a (canonical) query
+pub fn query_id(query: &str) -> QueryId {
+    let mut hasher = DefaultHasher::new();
+    query.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// What a worker parsed from the blocks it received
+struct Partial {
+    queries: HashMap<QueryId, String>,
+    variants: Variants,
+    entries: Vec<(Date, QueryId)>,
+    rejected_rows: usize
+}
+
+/// Parse a TSV log of (date, query) rows: blocks of whole lines are read by the calling thread
+/// while `workers` threads parse, normalize, hash and intern their rows, each sorting its entries
+/// once done. Lines that are not valid UTF-8 or (date, query) rows are skipped, read errors are
+/// fatal.
+pub fn parse<R: Read>(mut reader: R, workers: usize, normalization: Normalization, progress: &Progress) -> io::Result<Parsed> {
+    let workers = ::std::cmp::max(workers, 1);
+
+    // Bounded so that at most a few blocks wait in memory for a worker
+    let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(2 * workers);
+    let receiver = Mutex::new(receiver);
+
+    let (read, partials) = thread::scope(|scope| {
+        let handles: Vec<_> = (0 .. workers).map(|_| scope.spawn(|| parse_blocks(&receiver, normalization, progress)))
+                                            .collect();
+
+        // Dropping the sender once the log has been read stops the workers
+        let read = read_blocks(&mut reader, |block| sender.send(block).is_ok());
+        drop(sender);
+
+        let partials: Vec<Partial> = handles.into_iter()
+                                            .map(|handle| handle.join().unwrap())
+                                            .collect();
+        (read, partials)
+    });
+    read?;
+
+    let mut parsed = Parsed {
+        queries: HashMap::new(),
+        variants: HashMap::new(),
+        runs: Vec::with_capacity(partials.len()),
+        rejected_rows: 0
+    };
+    for partial in partials {
+        for (query_id, query) in partial.queries {
+            parsed.queries.entry(query_id).or_insert(query);
+        }
+        for (query_id, variants) in partial.variants {
+            let merged = parsed.variants.entry(query_id).or_default();
+            for (variant, count) in variants {
+                *merged.entry(variant).or_insert(0) += count;
+            }
+        }
+        parsed.runs.push(partial.entries);
+        parsed.rejected_rows += partial.rejected_rows;
+    }
+    Ok(parsed)
+}
+
+/// Read blocks of about `BLOCK_BYTES` made of whole lines, the last line of the log may have no
+/// line break. Stops early when `send` fails.
+fn read_blocks<R: Read, F: FnMut(Vec<u8>) -> bool>(reader: &mut R, mut send: F) -> io::Result<()> {
+    let mut carry: Vec<u8> = Vec::new();
+    loop {
+        let mut block = ::std::mem::take(&mut carry);
+        let start = block.len();
+        block.resize(start + BLOCK_BYTES, 0);
+        let read = match reader.read(&mut block[start ..]) {
+            Ok(read) => read,
+            Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {
+                carry = block;
+                carry.truncate(start);
+                continue;
+            },
+            Err(error) => return Err(error)
+        };
+        block.truncate(start + read);
+
+        if read == 0 {
+            if !block.is_empty() {
+                send(block);
+            }
+            return Ok(());
+        }
+
+        // Keep the incomplete last line for the next block
+        if let Some(end) = block.iter().rposition(|&byte| byte == b'\n') {
+            carry = block.split_off(end + 1);
+            if !send(block) {
+                return Ok(());
+            }
+        } else {
+            carry = block;
+        }
+    }
+}
+
+/// Parse the blocks received until the channel is closed
+fn parse_blocks(receiver: &Mutex<Receiver<Vec<u8>>>, normalization: Normalization, progress: &Progress) -> Partial {
+    const TSV_SEP: char = '\t';
+
+    let mut partial = Partial {
+        queries: HashMap::new(),
+        variants: HashMap::new(),
+        entries: Vec::new(),
+        rejected_rows: 0
+    };
+
+    loop {
+        // The lock is released as soon as a block has been received
+        let block = match receiver.lock().unwrap().recv() {
+            Ok(block) => block,
+            Err(_) => break
+        };
+
+        let mut rows = 0;
+        for line in block.split(|&byte| byte == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.is_empty() {
+                continue;
+            }
+            let row = str::from_utf8(line).ok()
+                                          .and_then(|line| line.split_once(TSV_SEP))
+                                          .and_then(|(date, query)| parse_date(date).map(|date| (date, query)));
+            let (date, query) = match row {
+                Some(row) => row,
+                None => {
+                    partial.rejected_rows += 1;
+                    continue;
+                }
+            };
+
+            let canonical = normalization.apply(query);
+            let query_hash = query_id(&canonical);
+
+            // Count raw variants, only allocating the first time one is seen
+            if !normalization.is_identity() {
+                let variants = partial.variants.entry(query_hash).or_default();
+                match variants.get_mut(query) {
+                    Some(count) => *count += 1,
+                    None => {
+                        variants.insert(String::from(query), 1);
+                    }
+                }
+            }
+
+            partial.queries.entry(query_hash).or_insert_with(|| canonical.into_owned());
+            partial.entries.push((date, query_hash));
+            rows += 1;
+        }
+        progress.add_rows(rows);
+    }
+
+    // Sorting: O(N/T log N/T) per worker
+    partial.entries.sort_unstable();
+    partial
+}
+
+/// Parse a `YYYY-MM-DD hh:mm:ss` date without going through a format string
+fn parse_date(date: &str) -> Option<Date> {
+    let bytes = date.as_bytes();
+    if bytes.len() != 19 || bytes[4] != b'-' || bytes[7] != b'-' || bytes[10] != b' ' || bytes[13] != b':' || bytes[16] != b':' {
+        return None;
+    }
+    let number = |from: usize, to: usize| -> Option<u32> {
+        bytes[from .. to].iter().try_fold(0, |number, &byte| match byte {
+            b'0' ..= b'9' => Some(number * 10 + u32::from(byte - b'0')),
+            _ => None
+        })
+    };
+    NaiveDate::from_ymd_opt(number(0, 4)? as i32, number(5, 7)?, number(8, 10)?)?
+              .and_hms_opt(number(11, 13)?, number(14, 16)?, number(17, 19)?)
+}
+
+/// Merge sorted runs of entries into a single sorted sequence, in O(N log R) for R runs
+pub fn merge_runs(runs: Vec<Vec<(Date, QueryId)>>) -> MergedRuns {
+    let mut runs: Vec<_> = runs.into_iter().map(Vec::into_iter).collect();
+    let heads = runs.iter_mut()
+                    .enumerate()
+                    .filter_map(|(run, entries)| entries.next().map(|entry| Reverse((entry, run))))
+                    .collect();
+    MergedRuns { runs, heads }
+}
+
+/// Iterator over the entries of sorted runs, in order
+pub struct MergedRuns {
+    runs: Vec<::std::vec::IntoIter<(Date, QueryId)>>,
+    heads: BinaryHeap<Reverse<((Date, QueryId), usize)>>  // Next entry of each run that is not exhausted
+}
+
+impl Iterator for MergedRuns {
+    type Item = (Date, QueryId);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((entry, run)) = self.heads.pop()?;
+        if let Some(next) = self.runs[run].next() {
+            self.heads.push(Reverse((next, run)));
+        }
+        Some(entry)
+    }
+}
@@ -0,0 +1,187 @@
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ self, Write };
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use chrono::{ SecondsFormat, Utc };
+
+use serde_json;
+
+use config::Config;
+
+/// Severity of a log line
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error"
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = ();
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        [Level::Debug, Level::Info, Level::Warn, Level::Error].iter()
+                                                              .cloned()
+                                                              .find(|level| level.name() == data)
+                                                              .ok_or(())
+    }
+}
+
+/// Formats of log lines
+#[derive(Clone, Copy)]
+pub enum LogFormat {
+    /// One JSON object per line
+    Json,
+    /// `key=value` pairs separated by spaces
+    Logfmt
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        match data {
+            "json" => Ok(LogFormat::Json),
+            "logfmt" => Ok(LogFormat::Logfmt),
+            _ => Err(())
+        }
+    }
+}
+
+/// Where log lines are written
+enum Sink {
+    Stdout,
+    /// A file rotated once it reaches `max_bytes`
+    File { path: String, file: File, bytes: u64, max_bytes: u64, keep: usize }
+}
+
+/// Structured logger, lines are made of a timestamp, a level, a message and fields
+pub struct Logger {
+    level: Level,
+    format: LogFormat,
+    sink: Mutex<Sink>
+}
+
+impl Logger {
+    pub fn new(config: &Config) -> io::Result<Self> {
+        let sink = match config.log_file {
+            None => Sink::Stdout,
+            Some(ref path) => {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                Sink::File {
+                    path: path.clone(),
+                    bytes: file.metadata()?.len(),
+                    file,
+                    max_bytes: config.log_max_bytes,
+                    keep: config.log_keep
+                }
+            }
+        };
+        Ok(Logger {
+            level: config.log_level,
+            format: config.log_format,
+            sink: Mutex::new(sink)
+        })
+    }
+
+    pub fn enabled(&self, level: Level) -> bool {
+        level >= self.level
+    }
+
+    pub fn log(&self, level: Level, message: &str, fields: &[(&str, serde_json::Value)]) {
+        if !self.enabled(level) {
+            return;
+        }
+
+        let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        let line = match self.format {
+            LogFormat::Json => {
+                let mut object = serde_json::Map::new();
+                object.insert("timestamp".to_string(), json!(timestamp));
+                object.insert("level".to_string(), json!(level.name()));
+                object.insert("message".to_string(), json!(message));
+                for &(name, ref value) in fields {
+                    object.insert(name.to_string(), value.clone());
+                }
+                format!("{}\n", serde_json::Value::Object(object))
+            },
+
+            LogFormat::Logfmt => {
+                let mut pairs = vec![
+                    format!("timestamp={}", timestamp),
+                    format!("level={}", level.name()),
+                    format!("message={}", logfmt_value(&json!(message)))
+                ];
+                pairs.extend(fields.iter().map(|&(name, ref value)| format!("{}={}", name, logfmt_value(value))));
+                format!("{}\n", pairs.join(" "))
+            }
+        };
+
+        // Logging never fails the caller, lines that cannot be written are lost
+        let _ = self.sink.lock().unwrap().write(&line);
+    }
+
+    pub fn info(&self, message: &str) {
+        self.log(Level::Info, message, &[]);
+    }
+
+    pub fn error(&self, message: &str) {
+        self.log(Level::Error, message, &[]);
+    }
+}
+
+impl Sink {
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        match *self {
+            Sink::Stdout => io::stdout().write_all(line.as_bytes()),
+
+            Sink::File { ref path, ref mut file, ref mut bytes, max_bytes, keep } => {
+                if *bytes > 0 && *bytes + line.len() as u64 > max_bytes {
+                    *file = rotate(path, keep)?;
+                    *bytes = 0;
+                }
+                file.write_all(line.as_bytes())?;
+                *bytes += line.len() as u64;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Shift `path.1`, …, `path.<keep - 1>` up by one, move `path` to `path.1` and reopen `path`,
+/// the oldest file being dropped
+fn rotate(path: &str, keep: usize) -> io::Result<File> {
+    if keep == 0 {
+        return File::create(path);
+    }
+    for index in (1..keep).rev() {
+        let from = format!("{}.{}", path, index);
+        if fs::metadata(&from).is_ok() {
+            fs::rename(&from, format!("{}.{}", path, index + 1))?;
+        }
+    }
+    fs::rename(path, format!("{}.1", path))?;
+    File::create(path)
+}
+
+/// Strings are quoted when they contain spaces, quotes or equal signs
+fn logfmt_value(value: &serde_json::Value) -> String {
+    match *value {
+        serde_json::Value::String(ref string) if string.is_empty() || string.contains(&[' ', '"', '=', '\n', '\t'][..]) => value.to_string(),
+        serde_json::Value::String(ref string) => string.clone(),
+        ref value => value.to_string()
+    }
+}
@@ -3,9 +3,14 @@ extern crate itertools; // group_by
 extern crate chrono; // date and time structures and operations
 
 extern crate hyper; // http server
-extern crate futures;
+#[macro_use]
+extern crate futures; // try_ready
+extern crate futures_cpupool; // worker threads
 extern crate url; 
 extern crate percent_encoding;
+extern crate unicode_normalization; // NFKC
+extern crate regex; // query filters
+extern crate tokio_signal; // unix signals
 
 #[macro_use]
 extern crate rouste; // routing
@@ -14,43 +19,135 @@ extern crate rouste; // routing
 extern crate serde_json; // json serialization
 
 pub mod time_range;
+#[macro_use]
 pub mod monoid;
 pub mod tree;
+pub mod normalize;
+pub mod filter;
+pub mod ingest;
 pub mod solver;
 pub mod utils;
+pub mod problem;
+pub mod api;
+pub mod format;
+pub mod metrics;
+pub mod state;
+pub mod config;
+pub mod logger;
+pub mod cache;
+pub mod pool;
+pub mod deadline;
 pub mod service;
 
-use service::handle_request;
+use service::{ handle_request, Context };
 use solver::Solver;
+use metrics::Metrics;
+use state::State;
+use config::Config;
+use logger::Logger;
+use pool::WorkerPool;
 
 use hyper::{ Server };
-use hyper::service::service_fn;
-use hyper::rt::Future;
+use hyper::server::conn::AddrStream;
+use hyper::service::{ make_service_fn, service_fn };
+use hyper::rt::{ Future, Stream };
+use futures::future;
+use tokio_signal::unix::{ Signal, SIGHUP, SIGTERM };
 
-const LOG_FILENAME: &'static str = "hn_logs.tsv";
+use std::process;
+use std::sync::Arc;
+use std::thread;
 
 fn main() {
-    println!("Preparing data structures");
-    match Solver::new(LOG_FILENAME) {
-        Ok(solver) => {
-            println!("Starting web server, go to http://127.0.0.1:8000");
-            let server_addr = ([127, 0, 0, 1], 8000).into();
-            let service = move || {
-                let solver = solver.clone();
-                service_fn(move |request| {
-                    println!("{} {:?}", request.method(), request.uri());
-                    handle_request(request, &solver)
-                })
-            };
-
-            let server = Server::bind(&server_addr)
-                .serve(service)
-                .map_err(|error| eprintln!("Server error: {}", error));
-
-            hyper::rt::run(server);
-        },
-        _ => {
-            eprintln!("Failed to load data");
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
         }
+    };
+    let logger = match Logger::new(&config) {
+        Ok(logger) => logger,
+        Err(error) => {
+            eprintln!("Failed to open the log file: {}", error);
+            process::exit(1);
+        }
+    };
+    let context = Arc::new(Context {
+        state: State::new(config.cache_entries),
+        metrics: Metrics::new(),
+        logger,
+        pool: WorkerPool::new(config.workers, config.worker_queue),
+        config
+    });
+
+    // Load the log in the background so that the server answers health checks meanwhile
+    load(&context);
+
+    context.logger.info("Starting web server, go to http://127.0.0.1:8000");
+    let server_addr = ([127, 0, 0, 1], 8000).into();
+    let server_context = Arc::clone(&context);
+    let service = make_service_fn(move |connection: &AddrStream| {
+        let client = connection.remote_addr();
+        let context = Arc::clone(&server_context);
+        service_fn(move |request| handle_request(request, client, &context))
+    });
+
+    // Signals can only be listened to from within the runtime
+    hyper::rt::run(future::lazy(move || {
+        // SIGTERM stops accepting connections, the server then waits for in-flight requests
+        let shutdown_context = Arc::clone(&context);
+        let shutdown = Signal::new(SIGTERM).flatten_stream()
+                                           .into_future()
+                                           .map(move |_| shutdown_context.logger.info("Shutting down, draining in-flight requests"))
+                                           .map_err(|_| ());
+
+        // SIGHUP reloads the log, the current solver answers requests until the new one is ready
+        let reload_context = Arc::clone(&context);
+        let error_context = Arc::clone(&context);
+        let reloads = Signal::new(SIGHUP).flatten_stream()
+                                         .for_each(move |_| {
+                                             load(&reload_context);
+                                             Ok(())
+                                         })
+                                         .map_err(move |error| error_context.logger.error(&format!("Failed to listen to SIGHUP: {}", error)));
+
+        let server = Server::bind(&server_addr)
+            .serve(service)
+            .with_graceful_shutdown(shutdown)
+            .map_err(move |error| context.logger.error(&format!("Server error: {}", error)));
+
+        // Listening to SIGHUP stops with the server
+        server.select(reloads)
+              .map(|_| ())
+              .map_err(|_| ())
+    }));
+}
+
+/// Build a solver from the configured log in a background thread and swap it in once it is
+/// ready. Failing to load the log is fatal unless a previous solver is serving requests.
+fn load(context: &Arc<Context>) {
+    if !context.state.start_loading() {
+        context.logger.info("The log is already being loaded");
+        return;
     }
+
+    let context = Arc::clone(context);
+    thread::spawn(move || {
+        let (state, logger) = (&context.state, &context.logger);
+        logger.info(&format!("Preparing data structures from {}", context.config.data_file));
+        match Solver::new(&context.config.data_file, context.config.solver_options(), state.progress()) {
+            Ok(solver) => {
+                state.set_solver(solver);
+                logger.info("Data structures ready");
+            },
+            Err(error) => {
+                logger.error(&format!("Failed to load data: {}", error));
+                if state.solver().is_none() {
+                    process::exit(1);
+                }
+            }
+        }
+        state.finish_loading();
+    });
 }
@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use hyper::StatusCode;
+
+use solver::Solver;
+
+/// Upper bounds of the buckets of the request duration histograms, in seconds
+const DURATION_BUCKETS: [f64; 10] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0, 5.0];
+
+/// Requests served for a route with a status
+#[derive(Default)]
+struct RequestStats {
+    buckets: [u64; DURATION_BUCKETS.len()],
+    sum: f64,
+    count: u64
+}
+
+/// Metrics of the service, exposed in the Prometheus text format
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<(&'static str, u16), RequestStats>>
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// Record a served request
+    pub fn observe(&self, route: &'static str, status: StatusCode, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let mut requests = self.requests.lock().unwrap();
+        let stats = requests.entry((route, status.as_u16())).or_default();
+        for (bucket, &upper_bound) in stats.buckets.iter_mut().zip(DURATION_BUCKETS.iter()) {
+            if seconds <= upper_bound {
+                *bucket += 1;
+            }
+        }
+        stats.sum += seconds;
+        stats.count += 1;
+    }
+
+    /// Render the metrics of the requests, of the jobs of the workers and of the index of the
+    /// solver, if it has been loaded
+    pub fn render(&self, solver: Option<&Solver>, worker_jobs: usize) -> String {
+        let mut text = String::new();
+        let requests = self.requests.lock().unwrap();
+
+        // Writing to a String never fails
+        writeln!(text, "# HELP algolia_http_requests_total Number of HTTP requests served.").unwrap();
+        writeln!(text, "# TYPE algolia_http_requests_total counter").unwrap();
+        for (&(route, status), stats) in requests.iter() {
+            writeln!(text, "algolia_http_requests_total{{route=\"{}\",status=\"{}\"}} {}", route, status, stats.count).unwrap();
+        }
+
+        writeln!(text, "# HELP algolia_http_request_duration_seconds Time spent computing responses.").unwrap();
+        writeln!(text, "# TYPE algolia_http_request_duration_seconds histogram").unwrap();
+        for (&(route, status), stats) in requests.iter() {
+            let labels = format!("route=\"{}\",status=\"{}\"", route, status);
+            for (count, upper_bound) in stats.buckets.iter().zip(DURATION_BUCKETS.iter()) {
+                writeln!(text, "algolia_http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels, upper_bound, count).unwrap();
+            }
+            writeln!(text, "algolia_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, stats.count).unwrap();
+            writeln!(text, "algolia_http_request_duration_seconds_sum{{{}}} {}", labels, stats.sum).unwrap();
+            writeln!(text, "algolia_http_request_duration_seconds_count{{{}}} {}", labels, stats.count).unwrap();
+        }
+
+        writeln!(text, "# HELP algolia_worker_jobs Number of scans running on the workers or waiting for them.").unwrap();
+        writeln!(text, "# TYPE algolia_worker_jobs gauge").unwrap();
+        writeln!(text, "algolia_worker_jobs {}", worker_jobs).unwrap();
+
+        // The index is only described once the log has been loaded
+        if let Some(solver) = solver {
+            let load_stats = solver.load_stats();
+            let gauges = [
+                ("algolia_index_queries", "Number of distinct queries in the index.", solver.distinct_queries() as f64),
+                ("algolia_index_dates", "Number of distinct dates in the index.", solver.distinct_dates() as f64),
+                ("algolia_index_occurrences", "Number of rows in the index.", solver.occurrences() as f64),
+                ("algolia_index_load_duration_seconds", "Time spent building the index.", load_stats.duration)
+            ];
+            for &(name, help, value) in gauges.iter() {
+                writeln!(text, "# HELP {} {}", name, help).unwrap();
+                writeln!(text, "# TYPE {} gauge", name).unwrap();
+                writeln!(text, "{} {}", name, value).unwrap();
+            }
+
+            writeln!(text, "# HELP algolia_index_rejected_rows_total Number of rows of the log that could not be parsed.").unwrap();
+            writeln!(text, "# TYPE algolia_index_rejected_rows_total counter").unwrap();
+            writeln!(text, "algolia_index_rejected_rows_total {}", load_stats.rejected_rows).unwrap();
+        }
+
+        text
+    }
+}
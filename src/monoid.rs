@@ -1,4 +1,217 @@
+use std::ops::{ Add, Sub };
+
 pub trait Monoid {
     fn m_empty() -> Self;
     fn m_append(&self, other: &Self) -> Self;
+
+    /// Append two owned values, which may reuse one of them instead of building a new value
+    fn m_concat(self, other: Self) -> Self where Self: Sized {
+        self.m_append(&other)
+    }
+}
+
+/// Commutative monoids whose values can be removed from an aggregate:
+/// `a.m_append(&b).m_remove(&b) == a`
+pub trait Group: Monoid {
+    fn m_remove(&self, other: &Self) -> Self;
+}
+
+/// Monoids for which appending a value to itself gives the same value, so that overlapping
+/// ranges can be appended
+pub trait Idempotent: Monoid {}
+
+impl Monoid for () {
+    fn m_empty() -> Self {}
+
+    fn m_append(&self, _: &Self) -> Self {}
+}
+
+impl Group for () {
+    fn m_remove(&self, _: &Self) -> Self {}
+}
+
+impl Idempotent for () {}
+
+// Numbers are appended by summing them
+macro_rules! sum_monoid {
+    ($($number: ty),*) => ($(
+        impl Monoid for $number {
+            fn m_empty() -> Self {
+                0 as $number
+            }
+
+            fn m_append(&self, other: &Self) -> Self {
+                self + other
+            }
+        }
+
+        impl Group for $number {
+            fn m_remove(&self, other: &Self) -> Self {
+                self - other
+            }
+        }
+    )*);
+}
+
+sum_monoid!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64);
+
+/// Sum of values, for types that are not monoids by themselves
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sum<T>(pub T);
+
+impl<T: Copy + Default + Add<Output=T>> Monoid for Sum<T> {
+    fn m_empty() -> Self {
+        Sum(T::default())
+    }
+
+    fn m_append(&self, other: &Self) -> Self {
+        Sum(self.0 + other.0)
+    }
+}
+
+impl<T: Copy + Default + Add<Output=T> + Sub<Output=T>> Group for Sum<T> {
+    fn m_remove(&self, other: &Self) -> Self {
+        Sum(self.0 - other.0)
+    }
+}
+
+/// Smallest value, if any
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Min<T>(pub Option<T>);
+
+impl<T: Copy + Ord> Monoid for Min<T> {
+    fn m_empty() -> Self {
+        Min(None)
+    }
+
+    fn m_append(&self, other: &Self) -> Self {
+        match (self.0, other.0) {
+            (Some(left), Some(right)) => Min(Some(::std::cmp::min(left, right))),
+            (left, right) => Min(left.or(right))
+        }
+    }
+}
+
+impl<T: Copy + Ord> Idempotent for Min<T> {}
+
+/// Largest value, if any
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Max<T>(pub Option<T>);
+
+impl<T: Copy + Ord> Monoid for Max<T> {
+    fn m_empty() -> Self {
+        Max(None)
+    }
+
+    fn m_append(&self, other: &Self) -> Self {
+        match (self.0, other.0) {
+            (Some(left), Some(right)) => Max(Some(::std::cmp::max(left, right))),
+            (left, right) => Max(left.or(right))
+        }
+    }
+}
+
+impl<T: Copy + Ord> Idempotent for Max<T> {}
+
+/// Leftmost value, if any
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct First<T>(pub Option<T>);
+
+impl<T: Copy> Monoid for First<T> {
+    fn m_empty() -> Self {
+        First(None)
+    }
+
+    fn m_append(&self, other: &Self) -> Self {
+        First(self.0.or(other.0))
+    }
+}
+
+impl<T: Copy> Idempotent for First<T> {}
+
+/// Rightmost value, if any
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Last<T>(pub Option<T>);
+
+impl<T: Copy> Monoid for Last<T> {
+    fn m_empty() -> Self {
+        Last(None)
+    }
+
+    fn m_append(&self, other: &Self) -> Self {
+        Last(other.0.or(self.0))
+    }
+}
+
+impl<T: Copy> Idempotent for Last<T> {}
+
+/// Missing values are neutral, present ones are appended
+impl<T: Monoid + Clone> Monoid for Option<T> {
+    fn m_empty() -> Self {
+        None
+    }
+
+    fn m_append(&self, other: &Self) -> Self {
+        match (self, other) {
+            (Some(left), Some(right)) => Some(left.m_append(right)),
+            (Some(value), None) | (None, Some(value)) => Some(value.clone()),
+            (None, None) => None
+        }
+    }
+}
+
+impl<T: Idempotent + Clone> Idempotent for Option<T> {}
+
+// Products of monoids are appended component-wise
+macro_rules! tuple_monoid {
+    ($($name: ident: $index: tt),*) => (
+        impl<$($name: Monoid),*> Monoid for ($($name,)*) {
+            fn m_empty() -> Self {
+                ($($name::m_empty(),)*)
+            }
+
+            fn m_append(&self, other: &Self) -> Self {
+                ($(self.$index.m_append(&other.$index),)*)
+            }
+        }
+
+        impl<$($name: Group),*> Group for ($($name,)*) {
+            fn m_remove(&self, other: &Self) -> Self {
+                ($(self.$index.m_remove(&other.$index),)*)
+            }
+        }
+
+        impl<$($name: Idempotent),*> Idempotent for ($($name,)*) {}
+    );
+}
+
+tuple_monoid!(A: 0, B: 1);
+tuple_monoid!(A: 0, B: 1, C: 2);
+tuple_monoid!(A: 0, B: 1, C: 2, D: 3);
+tuple_monoid!(A: 0, B: 1, C: 2, D: 3, E: 4);
+
+/// Declare a struct whose fields are monoids, appended field by field
+macro_rules! monoid {
+    ($(#[$attribute: meta])* $visibility: vis struct $name: ident {
+        $($(#[$field_attribute: meta])* $field_visibility: vis $field: ident: $field_type: ty),* $(,)*
+    }) => (
+        $(#[$attribute])*
+        $visibility struct $name {
+            $($(#[$field_attribute])* $field_visibility $field: $field_type),*
+        }
+
+        impl $crate::monoid::Monoid for $name {
+            fn m_empty() -> Self {
+                $name {
+                    $($field: <$field_type as $crate::monoid::Monoid>::m_empty()),*
+                }
+            }
+
+            fn m_append(&self, other: &Self) -> Self {
+                $name {
+                    $($field: $crate::monoid::Monoid::m_append(&self.$field, &other.$field)),*
+                }
+            }
+        }
+    );
 }
@@ -0,0 +1,130 @@
+use std::borrow::Cow;
+use std::str::FromStr;
+
+use percent_encoding::percent_decode;
+use unicode_normalization::UnicodeNormalization;
+use url::Url;
+
+/// Steps mapping raw queries to their canonical form before they are indexed
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Normalization {
+    pub trim: bool,                 // Remove leading and trailing whitespace
+    pub case_fold: bool,            // Lower case letters
+    pub nfkc: bool,                 // Unicode compatibility composition (NFKC)
+    pub collapse_whitespace: bool,  // Replace runs of whitespace with a single space
+    pub percent_decode: bool,       // Decode percent-encoded UTF-8
+    pub canonical_urls: bool        // Canonicalize http(s) URLs instead of applying the other steps
+}
+
+impl Normalization {
+    /// Whether queries are indexed as they are
+    pub fn is_identity(&self) -> bool {
+        *self == Normalization::default()
+    }
+
+    /// Canonical form of a query. Steps are applied in this order: percent-decoding, NFKC, case
+    /// folding, whitespace collapsing and trimming. URLs are only canonicalized (lower case scheme
+    /// and host, no default port nor fragment, normalized path) and trimmed.
+    pub fn apply<'a>(&self, query: &'a str) -> Cow<'a, str> {
+        if self.is_identity() {
+            return Cow::Borrowed(query);
+        }
+        if self.canonical_urls {
+            if let Some(url) = canonical_url(query.trim()) {
+                return Cow::Owned(url);
+            }
+        }
+
+        let mut query = Cow::Borrowed(query);
+        if self.percent_decode && query.contains('%') {
+            let decoded = percent_decode(query.as_bytes()).decode_utf8()
+                                                          .ok()
+                                                          .map(Cow::into_owned);
+            if let Some(decoded) = decoded {
+                query = Cow::Owned(decoded);
+            }
+        }
+        if self.nfkc && !query.is_ascii() {
+            query = Cow::Owned(query.nfkc().collect());
+        }
+        if self.case_fold && query.chars().any(char::is_uppercase) {
+            query = Cow::Owned(query.to_lowercase());
+        }
+        if self.collapse_whitespace && has_whitespace_run(&query) {
+            query = Cow::Owned(collapse_whitespace(&query));
+        }
+        if self.trim && query.trim().len() != query.len() {
+            query = Cow::Owned(query.trim().to_string());
+        }
+        query
+    }
+}
+
+/// Canonical form of absolute http(s) URLs
+fn canonical_url(query: &str) -> Option<String> {
+    let mut url = Url::parse(query).ok()?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return None;
+    }
+    url.set_fragment(None);
+    Some(url.as_str().to_string())
+}
+
+fn has_whitespace_run(query: &str) -> bool {
+    let mut previous_whitespace = false;
+    for c in query.chars() {
+        if c.is_whitespace() && (previous_whitespace || c != ' ') {
+            return true;
+        }
+        previous_whitespace = c.is_whitespace();
+    }
+    false
+}
+
+/// Replace each run of whitespace by a single space
+fn collapse_whitespace(query: &str) -> String {
+    let mut collapsed = String::with_capacity(query.len());
+    let mut previous_whitespace = false;
+    for c in query.chars() {
+        if c.is_whitespace() {
+            if !previous_whitespace {
+                collapsed.push(' ');
+            }
+        } else {
+            collapsed.push(c);
+        }
+        previous_whitespace = c.is_whitespace();
+    }
+    collapsed
+}
+
+/// Steps are given as a comma-separated list of `trim`, `case`, `nfkc`, `whitespace`, `percent`
+/// and `url`, or as `none` or `all`
+impl FromStr for Normalization {
+    type Err = ();
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        let mut normalization = Normalization::default();
+        for step in data.split(',').map(str::trim) {
+            match step {
+                "none" => (),
+                "all" => normalization = Normalization {
+                    trim: true,
+                    case_fold: true,
+                    nfkc: true,
+                    collapse_whitespace: true,
+                    percent_decode: true,
+                    canonical_urls: true
+                },
+                "trim" => normalization.trim = true,
+                "case" => normalization.case_fold = true,
+                "nfkc" => normalization.nfkc = true,
+                "whitespace" => normalization.collapse_whitespace = true,
+                "percent" => normalization.percent_decode = true,
+                "url" => normalization.canonical_urls = true,
+                _ => return Err(())
+            }
+        }
+        Ok(normalization)
+    }
+}
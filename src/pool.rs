@@ -0,0 +1,68 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+
+use futures::{ Future, Sink, Stream };
+use futures::stream;
+use futures::sync::mpsc;
+use futures_cpupool::{ Builder, CpuFuture, CpuPool };
+
+use hyper::Body;
+
+/// Threads running the CPU-heavy work of requests away from the threads serving connections.
+/// At most `queue` jobs wait for a worker, more are rejected.
+pub struct WorkerPool {
+    pool: CpuPool,
+    capacity: usize,                        // Jobs running or waiting
+    jobs: Arc<AtomicUsize>
+}
+
+/// Releases the place of a job in the pool once it is done, even if it panicked
+struct JobGuard {
+    jobs: Arc<AtomicUsize>
+}
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        self.jobs.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl WorkerPool {
+    pub fn new(workers: usize, queue: usize) -> Self {
+        WorkerPool {
+            pool: Builder::new().pool_size(workers).name_prefix("worker-").create(),
+            capacity: workers + queue,
+            jobs: Arc::new(AtomicUsize::new(0))
+        }
+    }
+
+    /// Run `job` on a worker, unless all of them are busy and the queue is full
+    pub fn run<T, E, F>(&self, job: F) -> Option<CpuFuture<T, E>>
+        where T: Send + 'static, E: Send + 'static, F: FnOnce() -> T + Send + 'static {
+        if self.jobs.fetch_add(1, Ordering::SeqCst) >= self.capacity {
+            self.jobs.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        let guard = JobGuard { jobs: Arc::clone(&self.jobs) };
+        Some(self.pool.spawn_fn(move || {
+            let _guard = guard;
+            Ok(job())
+        }))
+    }
+
+    /// Number of jobs running or waiting for a worker
+    pub fn jobs(&self) -> usize {
+        self.jobs.load(Ordering::SeqCst)
+    }
+
+    /// Render the chunks of a streamed body on the workers, a chunk is only rendered once the
+    /// previous one has been taken by the connection
+    pub fn stream(&self, chunks: Box<dyn Iterator<Item=String> + Send>) -> Body {
+        let (sender, receiver) = mpsc::channel(0);
+        let rendering = stream::iter_ok::<_, ()>(chunks).forward(sender.sink_map_err(|_| ()))
+                                                       .map(|_| ());
+        self.pool.spawn(rendering).forget();
+        Body::wrap_stream(receiver.map_err(|_| io::Error::other("the rendering of the body stopped")))
+    }
+}
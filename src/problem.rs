@@ -0,0 +1,89 @@
+use hyper::StatusCode;
+
+use serde_json;
+
+use deadline::Expired;
+
+/// An error reported to clients as a problem details object (RFC 7807)
+pub struct Problem {
+    pub status: StatusCode,
+    pub detail: String,
+    pub instance: Option<String>,
+    invalid_param: Option<(&'static str, String)>
+}
+
+impl Problem {
+    pub fn new(status: StatusCode, detail: String) -> Self {
+        Problem {
+            status,
+            detail,
+            instance: None,
+            invalid_param: None
+        }
+    }
+
+    /// No route matches the requested path
+    pub fn not_found(path: &str) -> Self {
+        Problem::new(StatusCode::NOT_FOUND, format!("No resource at {}", path))
+    }
+
+    /// A parameter could not be decoded, `reason` tells what was expected instead
+    pub fn invalid_param(name: &'static str, value: &str, reason: String) -> Self {
+        Problem {
+            status: StatusCode::BAD_REQUEST,
+            detail: format!("Invalid value '{}' for parameter '{}'", value, name),
+            instance: None,
+            invalid_param: Some((name, reason))
+        }
+    }
+
+    /// The requested API version does not exist
+    pub fn unsupported_version(version: u32) -> Self {
+        Problem::new(StatusCode::NOT_FOUND, format!("API version {} is not supported", version))
+    }
+
+    /// The requested API version is not served anymore
+    pub fn retired_version(version: u32) -> Self {
+        Problem::new(StatusCode::GONE, format!("API version {} has been retired", version))
+    }
+
+    /// The request is larger than what the service accepts
+    pub fn too_large(detail: String) -> Self {
+        Problem::new(StatusCode::PAYLOAD_TOO_LARGE, detail)
+    }
+
+    /// The service cannot answer the request yet
+    pub fn unavailable(detail: String) -> Self {
+        Problem::new(StatusCode::SERVICE_UNAVAILABLE, detail)
+    }
+
+    /// The request would cost more than what the service accepts
+    pub fn too_expensive(detail: String) -> Self {
+        Problem::new(StatusCode::TOO_MANY_REQUESTS, detail)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut problem = json!({
+            "type": "about:blank",
+            "title": self.status.canonical_reason().unwrap_or_default(),
+            "status": self.status.as_u16(),
+            "detail": self.detail
+        });
+        if let Some(ref instance) = self.instance {
+            problem["instance"] = json!(instance);
+        }
+        if let Some((name, ref reason)) = self.invalid_param {
+            problem["invalid-params"] = json!([{
+                "name": name,
+                "reason": reason
+            }]);
+        }
+        problem
+    }
+}
+
+impl From<Expired> for Problem {
+    fn from(_: Expired) -> Self {
+        Problem::unavailable("The request took too long to answer".to_string())
+    }
+}
@@ -1,100 +1,907 @@
-use solver::Solver;
-use time_range::TimeRange;
+use solver::{ Solver, TrendScore };
+use filter::QueryFilter;
+use time_range::{ Granularity, TimeRange };
+use problem::Problem;
+use api::{ self, Api, Status };
+use format::{ Content, Format, Output };
+use metrics::Metrics;
+use logger::{ Level, Logger };
+use state::State;
+use config::Config;
+use cache::ResultCache;
+use pool::WorkerPool;
+use deadline::Deadline;
+use utils::param::Param;
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{ Hash, Hasher };
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{ Duration, Instant };
 
 use hyper;
-use hyper::{ Body, Request, Response, StatusCode };
-use hyper::header::{ HeaderValue, CONTENT_TYPE };
-use hyper::rt::Future;
-use futures::future;
+use hyper::{ Body, Method, Request, Response, StatusCode };
+use hyper::body::Payload;
+use hyper::header::{ HeaderName, HeaderValue, ACCEPT, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_NONE_MATCH, VARY };
+use hyper::rt::{ Future, Stream };
+use futures::{ future, Async, Poll };
 
 use serde_json;
 
 use rouste::utils::*;
 
-type BoxedFuture = Box<Future<Item=Response<Body>, Error=hyper::Error> + Send>;
+type BoxedFuture = Box<dyn Future<Item=Response<Body>, Error=hyper::Error> + Send>;
 
 type ContentType = &'static str;
 const CONTENT_TYPE_TEXT: ContentType = "text/plain";
+const CONTENT_TYPE_PROBLEM: ContentType = "application/problem+json";
 const CONTENT_TYPE_JSON: ContentType = "application/json";
+const CONTENT_TYPE_METRICS: ContentType = "text/plain; version=0.0.4";
+
+type HandlerResult = Result<(ContentType, Content, StatusCode), Problem>;
+type OutputResult = Result<Output, Problem>;
+
+const MAX_BATCH_BYTES: usize = 1 << 20;
+const MAX_BATCH_OPERATIONS: usize = 1000;
+
+/// Routes of versioned paths, as labelled in metrics
+const VERSIONED_ROUTES: [(&str, &str); 8] = [ ("queries/count", "/<version>/queries/count")
+                                            , ("queries/popular", "/<version>/queries/popular")
+                                            , ("queries/completions", "/<version>/queries/completions")
+                                            , ("queries/variants", "/<version>/queries/variants")
+                                            , ("queries/trending", "/<version>/queries/trending")
+                                            , ("queries/anomalies", "/<version>/queries/anomalies")
+                                            , ("queries/batch", "/<version>/queries/batch")
+                                            , ("logs", "/<version>/logs")
+                                            ];
+
+/// Routes that do not depend on an API version, as labelled in metrics
+const UNVERSIONED_ROUTES: [&str; 4] = ["/", "/metrics", "/healthz", "/readyz"];
+
+/// What is shared between requests
+pub struct Context {
+    pub config: Config,
+    pub state: State,
+    pub metrics: Metrics,
+    pub logger: Logger,
+    pub pool: WorkerPool
+}
+
+/// What the handlers of a versioned route answer with: the solver, the cache of its results and
+/// the limits of the request
+struct Scope<'a> {
+    solver: &'a Arc<Solver>,
+    cache: &'a ResultCache,
+    deadline: Deadline,
+    max_size: usize,
+    max_cost: usize
+}
+
+impl<'a> Scope<'a> {
+    /// Scope of a request starting now
+    fn new(context: &'a Context, solver: &'a Arc<Solver>) -> Self {
+        Scope {
+            solver,
+            cache: context.state.cache(),
+            deadline: Deadline::after(Duration::from_millis(context.config.request_timeout_ms)),
+            max_size: context.config.max_size,
+            max_cost: context.config.max_cost
+        }
+    }
+
+    fn check_size(&self, size: usize) -> Result<usize, Problem> {
+        if size > self.max_size {
+            return Err(Problem::invalid_param("size", &size.to_string(), format!("expected at most {}", self.max_size)));
+        }
+        Ok(size)
+    }
+
+    /// Reject scans of more occurrences than the budget, the number of occurrences of a range is
+    /// found in O(log N) before scanning it
+    fn check_cost(&self, time_ranges: &[&TimeRange]) -> Result<(), Problem> {
+        if self.max_cost == 0 {
+            return Ok(());
+        }
+        let cost: usize = time_ranges.iter()
+                                     .map(|time_range| self.solver.query_count(&time_range.from, &time_range.to))
+                                     .sum();
+        if cost > self.max_cost {
+            return Err(Problem::too_expensive(format!("The request would scan {} occurrences, at most {} are allowed",
+                                                      cost, self.max_cost)));
+        }
+        Ok(())
+    }
+}
+
+/// Handle a request, record it in the metrics once its response is ready and in the access log
+/// once it has been sent
+pub fn handle_request(req: Request<Body>, client: SocketAddr, context: &Arc<Context>) -> BoxedFuture {
+    let start = Instant::now();
+    let route = route_label(req.uri().path());
+    let context = Arc::clone(context);
+    let access = AccessLog {
+        context: Arc::clone(&context),
+        method: req.method().to_string(),
+        path: req.uri().path_and_query().map_or("/", |path| path.as_str()).to_string(),
+        client,
+        start,
+        status: StatusCode::OK,
+        bytes: 0
+    };
+    let response = route_request(req, &context).map(move |response| {
+        context.metrics.observe(route, response.status(), start.elapsed());
+        log_access(access, response)
+    });
+    Box::new(response)
+}
+
+/// Access log line of a request, written when it is dropped along with the response body
+struct AccessLog {
+    context: Arc<Context>,
+    method: String,
+    path: String,
+    client: SocketAddr,
+    start: Instant,
+    status: StatusCode,
+    bytes: u64
+}
+
+impl Drop for AccessLog {
+    fn drop(&mut self) {
+        let level = if self.status.is_server_error() {
+            Level::Error
+        } else if self.status.is_client_error() {
+            Level::Warn
+        } else {
+            Level::Info
+        };
+        self.context.logger.log(level, "request", &[
+            ("method", json!(self.method)),
+            ("path", json!(self.path)),
+            ("status", json!(self.status.as_u16())),
+            ("latency_ms", json!(self.start.elapsed().as_secs_f64() * 1000.0)),
+            ("bytes", json!(self.bytes)),
+            ("client", json!(self.client.to_string()))
+        ]);
+    }
+}
+
+/// Log the access once the body of the response has been sent, streamed bodies are counted
+/// chunk after chunk
+fn log_access(mut access: AccessLog, response: Response<Body>) -> Response<Body> {
+    access.status = response.status();
+    let (parts, body) = response.into_parts();
+    match body.content_length() {
+        Some(length) => {
+            access.bytes = length;
+            Response::from_parts(parts, body)
+        },
+        None => Response::from_parts(parts, Body::wrap_stream(CountedBody { body, access }))
+    }
+}
+
+/// Body counting the bytes of its chunks into an access log
+struct CountedBody {
+    body: Body,
+    access: AccessLog
+}
+
+impl Stream for CountedBody {
+    type Item = hyper::Chunk;
+    type Error = hyper::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let chunk = try_ready!(self.body.poll());
+        if let Some(ref chunk) = chunk {
+            self.access.bytes += chunk.len() as u64;
+        }
+        Ok(Async::Ready(chunk))
+    }
+}
+
+/// Label of the route of a path in metrics, parameters are left out so that there is a bounded
+/// number of labels
+fn route_label(path: &str) -> &'static str {
+    if let Some(&route) = UNVERSIONED_ROUTES.iter().find(|&&route| route == path) {
+        return route;
+    }
+    let rest = match path.trim_start_matches('/').split_once('/') {
+        Some((version, rest)) if version.parse::<u32>().is_ok() => rest,
+        _ => return "unknown"
+    };
+    VERSIONED_ROUTES.iter()
+                    .find(|&&(route, _)| rest == route || rest.strip_prefix(route).is_some_and(|end| end.starts_with('/')))
+                    .map_or("unknown", |&(_, label)| label)
+}
 
 /// Decode URI and box response for hyper
-pub fn handle_request(req: Request<Body>, solver: &Solver) -> BoxedFuture {
-    // Bind handlers with the solver
-    let binded_handle_count = |version: u32, time_range: TimeRange, distinct: Option<()>| {
-        handle_count(solver, version, time_range, distinct)
+fn route_request(req: Request<Body>, context: &Arc<Context>) -> BoxedFuture {
+    let path = req.uri().path().to_string();
+    let uri = format!("{}?{}", path, req.uri().query().unwrap_or_default());
+    let accept = req.headers()
+                    .get(ACCEPT)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string);
+
+    // Versioned routes are matched without their version segment
+    let (api, uri) = match api::resolve(&uri) {
+        Ok((Some(api), _)) if api.status == Status::Retired => {
+            let problem = Problem::retired_version(api.version);
+            return Box::new(future::ok(make_response(&context.pool, Some(api), &path, Err(problem))));
+        },
+        Ok(resolved) => resolved,
+        Err(problem) => return Box::new(future::ok(make_response(&context.pool, None, &path, Err(problem))))
     };
 
-    let binded_handle_popular = |version: u32, time_range: TimeRange, size: Option<usize>| {
-        handle_popular(solver, version, time_range, size)
+    if *req.method() == Method::POST {
+        return handle_post(req, uri, api, accept, context);
+    }
+
+    // Responses of versioned routes only depend on the request and on the index, they can be
+    // revalidated without being computed again
+    let solver = context.state.solver();
+    let etag = match (api, solver.as_ref()) {
+        (Some(api), Some(solver)) => Some(entity_tag(api, uri, accept.as_deref(), solver.generation())),
+        _ => None
     };
+    let max_age = context.config.cache_max_age;
+    if let Some(ref etag) = etag {
+        let if_none_match = req.headers()
+                               .get(IF_NONE_MATCH)
+                               .and_then(|value| value.to_str().ok());
+        if if_none_match.is_some_and(|tags| matches_entity_tag(tags, etag)) {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+            add_validators(&mut response, etag, max_age);
+            return Box::new(future::ok(response));
+        }
+    }
 
-    let uri = format!("{}?{}", req.uri().path(), req.uri().query().unwrap_or_default());
+    let heavy = scans_index(route_label(&path), req.uri().query());
+    let uri = uri.to_string();
+    let job_path = path.clone();
+    let job_context = Arc::clone(context);
+    let respond = move || {
+        let (context, path) = (job_context, job_path);
+        let result = handle_get(&uri, api, accept.as_deref(), &context, solver.as_ref()).unwrap_or_else(|| Err(Problem::not_found(&path)));
+        let mut response = make_response(&context.pool, api, &path, result);
+        if let Some(ref etag) = etag {
+            if response.status() == StatusCode::OK {
+                add_validators(&mut response, etag, max_age);
+            }
+        }
+        response
+    };
+
+    // Cheap requests are answered right away, scans run on the workers
+    if heavy {
+        offload(context, api, &path, respond)
+    } else {
+        Box::new(future::ok(respond()))
+    }
+}
+
+/// Whether a request scans the index, as opposed to answering from the segment tree or without
+/// the index at all
+fn scans_index(route: &str, query: Option<&str>) -> bool {
+    match route {
+        "/<version>/queries/count" => query.is_some_and(|query| query.split('&').any(|param| param == "distinct" || param.starts_with("filter="))),
+        "/<version>/queries/variants" => false,
+        _ => route.starts_with("/<version>/")
+    }
+}
+
+/// Answer a request on the workers, or with a 503 when they are all busy and the queue is full
+fn offload<F: FnOnce() -> Response<Body> + Send + 'static>(context: &Context, api: Option<&Api>, path: &str,
+                                                           respond: F) -> BoxedFuture {
+    match context.pool.run(respond) {
+        Some(response) => Box::new(response),
+        None => {
+            let problem = Problem::unavailable("All workers are busy, retry later".to_string());
+            Box::new(future::ok(make_response(&context.pool, api, path, Err(problem))))
+        }
+    }
+}
+
+/// Weak entity tag of the response to a request of an index generation
+fn entity_tag(api: &Api, uri: &str, accept: Option<&str>, generation: u64) -> String {
+    let mut hasher = DefaultHasher::new();
+    (api.version, uri, accept).hash(&mut hasher);
+    format!("W/\"{}-{:016x}\"", generation, hasher.finish())
+}
+
+/// Whether an `If-None-Match` header lists an entity tag, using the weak comparison
+fn matches_entity_tag(tags: &str, etag: &str) -> bool {
+    let opaque_tag = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    tags.split(',').any(|tag| tag.trim() == "*" || opaque_tag(tag) == opaque_tag(etag))
+}
+
+fn add_validators(response: &mut Response<Body>, etag: &str, max_age: u64) {
+    let headers = response.headers_mut();
+    if let Ok(etag) = HeaderValue::from_str(etag) {
+        headers.insert(ETAG, etag);
+    }
+    if let Ok(cache_control) = HeaderValue::from_str(&format!("public, max-age={}", max_age)) {
+        headers.insert(CACHE_CONTROL, cache_control);
+    }
+    headers.insert(VARY, HeaderValue::from_static("Accept"));
+}
+
+/// Route POST requests, their body has to be received before being handled
+fn handle_post(req: Request<Body>, uri: &str, api: Option<&'static Api>, accept: Option<String>,
+               context: &Arc<Context>) -> BoxedFuture {
+    let path = req.uri().path().to_string();
+    let batch = |format: Option<Param<Format>>| format;
+
+    let router = route_with![ route!(/queries/batch?(format: Param<Format>) => batch) ];
+
+    let (api, format) = match (api, router(uri)) {
+        (Some(api), Some(format)) => (api, format),
+        _ => return Box::new(future::ok(make_response(&context.pool, api, &path, Err(Problem::not_found(&path)))))
+    };
+
+    let solver = match context.state.solver() {
+        Some(solver) => solver,
+        None => return Box::new(future::ok(make_response(&context.pool, Some(api), &path, Err(loading(&context.state)))))
+    };
+
+    // Reject announced oversized bodies before receiving them
+    let content_length = req.headers()
+                            .get(CONTENT_LENGTH)
+                            .and_then(|value| value.to_str().ok())
+                            .and_then(|value| value.parse::<usize>().ok());
+    if content_length.is_some_and(|length| length > MAX_BATCH_BYTES) {
+        return Box::new(future::ok(make_response(&context.pool, Some(api), &path, Err(batch_too_large()))));
+    }
+
+    // Keep receiving the body once it is too large, but stop buffering it
+    let context = Arc::clone(context);
+    let response = req.into_body()
+                      .fold((Vec::new(), false), |(mut body, too_large), chunk| {
+                          let too_large = too_large || body.len() + chunk.len() > MAX_BATCH_BYTES;
+                          if !too_large {
+                              body.extend_from_slice(&chunk);
+                          }
+                          Ok::<_, hyper::Error>((body, too_large))
+                      })
+                      .and_then(move |(body, too_large)| -> BoxedFuture {
+                          if too_large {
+                              return Box::new(future::ok(make_response(&context.pool, Some(api), &path, Err(batch_too_large()))));
+                          }
+                          let job_context = Arc::clone(&context);
+                          let job_path = path.clone();
+                          offload(&context, Some(api), &path, move || {
+                              let scope = Scope::new(&job_context, &solver);
+                              let result = render(handle_batch(&scope, api, &body), format, accept.as_deref());
+                              make_response(&job_context.pool, Some(api), &job_path, result)
+                          })
+                      });
+    Box::new(response)
+}
+
+fn batch_too_large() -> Problem {
+    Problem::too_large(format!("Batch bodies are limited to {} bytes", MAX_BATCH_BYTES))
+}
+
+/// Route GET requests
+fn handle_get(uri: &str, api: Option<&'static Api>, accept: Option<&str>, context: &Context,
+              solver: Option<&Arc<Solver>>) -> Option<HandlerResult> {
+    let state = &context.state;
+
+    let binded_handle_metrics = || handle_metrics(solver.map(|solver| &**solver), context);
+    let binded_handle_healthz = || handle_healthz(state);
+    let binded_handle_readyz = || handle_readyz(state);
 
     let router = route_with![ route!(/ => handle_default)
-                            , route!(/(version: u32)/queries/count/(time_range: TimeRange)?distinct => binded_handle_count)
-                            , route!(/(version: u32)/queries/popular/(time_range: TimeRange)?(size: usize) => binded_handle_popular)
+                            , route!(/metrics => binded_handle_metrics)
+                            , route!(/healthz => binded_handle_healthz)
+                            , route!(/readyz => binded_handle_readyz)
                             ];
 
-    let mut response = Response::new(Body::empty());
+    match (api, solver) {
+        (Some(api), Some(solver)) => handle_versioned_get(uri, api, accept, &Scope::new(context, solver)),
+        (Some(_), None) => Some(Err(loading(state))),
+        (None, _) => router(uri)
+    }
+}
 
-    match router(&uri) {
-        Some((content_type, content, status)) => {
-            response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
-            *response.body_mut() = Body::from(content);
-            *response.status_mut() = status;
-        },
+/// Requests that need the solver are answered with a 503 while the log is being loaded
+fn loading(state: &State) -> Problem {
+    let progress = state.progress();
+    Problem::unavailable(format!("The index is being loaded ({:.0}%, {} rows)", progress.percent(), progress.rows()))
+}
 
-        None => {
-            *response.status_mut() = StatusCode::NOT_FOUND;
+/// Route GET requests of an API version
+fn handle_versioned_get(uri: &str, api: &'static Api, accept: Option<&str>, scope: &Scope) -> Option<HandlerResult> {
+    // Bind handlers with the scope and the API version, and render their output
+    let binded_handle_count = |time_range: Param<TimeRange>, distinct: Option<()>, stats: Option<()>, filter: Option<String>,
+                               ignore_case: Option<()>, regex: Option<()>, format: Option<Param<Format>>| {
+        render(handle_count(scope, api, time_range, distinct, stats, filter, ignore_case, regex), format, accept)
+    };
+
+    let binded_handle_popular = |time_range: Param<TimeRange>, size: Option<Param<usize>>, filter: Option<String>,
+                                 ignore_case: Option<()>, regex: Option<()>, format: Option<Param<Format>>| {
+        render(handle_popular(scope, api, time_range, size, filter, ignore_case, regex), format, accept)
+    };
+
+    let binded_handle_trending = |baseline: Param<TimeRange>, target: Param<TimeRange>, size: Option<Param<usize>>,
+                                  min_support: Option<Param<usize>>, score: Option<Param<TrendScore>>,
+                                  format: Option<Param<Format>>| {
+        render(handle_trending(scope, api, baseline, target, size, min_support, score), format, accept)
+    };
+
+    let binded_handle_anomalies = |time_range: Param<TimeRange>, granularity: Option<Param<Granularity>>,
+                                   window: Option<Param<usize>>, threshold: Option<Param<f64>>,
+                                   format: Option<Param<Format>>| {
+        render(handle_anomalies(scope, api, time_range, granularity, window, threshold), format, accept)
+    };
+
+    let binded_handle_completions = |time_range: Param<TimeRange>, prefix: Option<String>, size: Option<Param<usize>>,
+                                     format: Option<Param<Format>>| {
+        render(handle_completions(scope, api, time_range, prefix, size), format, accept)
+    };
+
+    let binded_handle_variants = |query: String, format: Option<Param<Format>>| {
+        render(handle_variants(scope.solver, query), format, accept)
+    };
+
+    let binded_handle_logs = |time_range: Param<TimeRange>, contains: Option<String>, format: Option<Param<Format>>| {
+        render(handle_logs(scope.solver, api, time_range, contains), format, accept)
+    };
+
+    let router = route_with![ route!(/queries/count/(time_range: Param<TimeRange>)?distinct&stats&(filter: String)&ignore_case&regex&(format: Param<Format>) => binded_handle_count)
+                            , route!(/queries/popular/(time_range: Param<TimeRange>)?(size: Param<usize>)&(filter: String)&ignore_case&regex&(format: Param<Format>) => binded_handle_popular)
+                            , route!(/queries/trending/(baseline: Param<TimeRange>)/(target: Param<TimeRange>)?(size: Param<usize>)&(min_support: Param<usize>)&(score: Param<TrendScore>)&(format: Param<Format>) => binded_handle_trending)
+                            , route!(/queries/anomalies/(time_range: Param<TimeRange>)?(granularity: Param<Granularity>)&(window: Param<usize>)&(threshold: Param<f64>)&(format: Param<Format>) => binded_handle_anomalies)
+                            , route!(/queries/completions/(time_range: Param<TimeRange>)?(prefix: String)&(size: Param<usize>)&(format: Param<Format>) => binded_handle_completions)
+                            , route!(/queries/variants/(query: String)?(format: Param<Format>) => binded_handle_variants)
+                            , route!(/logs/(time_range: Param<TimeRange>)?(contains: String)&(format: Param<Format>) => binded_handle_logs)
+                            ];
+
+    router(uri)
+}
+
+/// Render the output of a handler in the format requested by the `format` parameter or the
+/// `Accept` header
+fn render(output: OutputResult, format: Option<Param<Format>>, accept: Option<&str>) -> HandlerResult {
+    let format = Param::get_optional(format, "format")?;
+    let output = output?;
+    let format = Format::negotiate(format, accept, output.supported_formats())?;
+    Ok((format.content_type(), output.render(format), StatusCode::OK))
+}
+
+/// Build the response of a handler, problems are serialized as problem details and streamed
+/// bodies are rendered on the workers
+fn make_response(pool: &WorkerPool, api: Option<&Api>, path: &str, result: HandlerResult) -> Response<Body> {
+    let (content_type, content, status) = result.unwrap_or_else(|mut problem| {
+        if api.is_some_and(|api| api.problem_instance) {
+            problem.instance = Some(path.to_string());
         }
+        (CONTENT_TYPE_PROBLEM, Content::Full(problem.to_json().to_string()), problem.status)
+    });
+    let body = match content {
+        Content::Full(content) => Body::from(content),
+        Content::Stream(chunks) => pool.stream(chunks)
+    };
+    let mut response = Response::new(body);
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    if api.is_some_and(|api| api.status != Status::Current) {
+        response.headers_mut().insert(HeaderName::from_static("deprecation"), HeaderValue::from_static("true"));
     }
-    Box::new(future::ok(response))
+    *response.status_mut() = status;
+    response
 }
 
-const DEFAULT_CONTENT: &'static str = "# Algolia interview challenge
+const DEFAULT_CONTENT: &str = "# Algolia interview challenge
 
 ## Types
 
 - u32: 32 bits unsigned integer
 - TimeRange: YYYY[-MM[-DD[ hh[:mm]]]]
 
+## Versions
+
+- 1: deprecated, responses carry a Deprecation header
+- 2: dates are formatted as YYYY-MM-DDThh:mm:ss, popular queries are sorted by decreasing count and problem details
+  give the path of the failed request
+
 ## Number of queries in a time range
 
-Endpoint: /<version: u32>/queries/count/<time range: TimeRange>[?[distinct]]
+Endpoint: /<version: u32>/queries/count/<time range: TimeRange>[?[distinct][&stats][&<filter>]]
+
+With `stats`, the dates of the first and last queries of the time range are given as `first` and `last`.
 
 ## K most frequent queries in a time range
 
-Endpoint: /<version: u32>/queries/popular/<time range: TimeRange>[?[size=<u32>]]";
+Endpoint: /<version: u32>/queries/popular/<time range: TimeRange>[?[size=<u32>][&<filter>]]
+
+## Filters
+
+Filter: filter=<percent-encoded string>[&ignore_case][&regex]
+
+Counts and popular queries only take into account the queries containing the string, or matching it as a regular
+expression with `regex`. With `ignore_case`, letters match regardless of their case. Filtered counts scan the time range
+and cannot give `stats`.
+
+## K queries with the highest growth between a baseline and a target time range
+
+Endpoint: /<version: u32>/queries/trending/<baseline: TimeRange>/<target: TimeRange>[?[size=<u32>][&min_support=<u32>][&score=absolute|relative]]
+
+## Time buckets whose number of queries deviates from the preceding buckets
+
+Endpoint: /<version: u32>/queries/anomalies/<time range: TimeRange>[?[granularity=minute|hour][&window=<u32>][&threshold=<f64>]]
+
+## K most frequent queries starting with a prefix in a time range
+
+Endpoint: /<version: u32>/queries/completions/<time range: TimeRange>[?[prefix=<percent-encoded string>][&size=<u32>]]
+
+## Raw queries merged into a query by normalization, most frequent first
+
+Endpoint: /<version: u32>/queries/variants/<query: percent-encoded string>
+
+## Rows of the log in a time range, optionally restricted to queries containing a string
+
+Endpoint: /<version: u32>/logs/<time range: TimeRange>[?[contains=<string>][&format=tsv|ndjson|csv|json]]
+
+## Several count, distinct and popular queries at once
+
+Endpoint: POST /<version: u32>/queries/batch
+
+Body: a JSON array of operations, for instance:
 
-fn handle_default() -> (ContentType, String, StatusCode) {
-    (CONTENT_TYPE_TEXT, DEFAULT_CONTENT.to_string(), StatusCode::OK)
+    [ { \"op\": \"count\", \"range\": \"2015-08-01\" }
+    , { \"op\": \"distinct\", \"range\": \"2015-08-01 00:04\" }
+    , { \"op\": \"popular\", \"range\": \"2015-08\", \"size\": 5 }
+    ]
+
+Operations can be filtered with \"filter\": <string>, \"ignore_case\": true and \"regex\": true. Results are returned in the
+same order, an operation that fails is replaced by { \"error\": <message> }
+
+## Formats
+
+Responses are JSON documents by default. The format=json|csv|tsv|ndjson parameter, or the Accept header
+(application/json, text/csv, text/tab-separated-values, application/x-ndjson), selects another representation: CSV, TSV
+and NDJSON give one line per count, query, trend, anomaly or row. TSV has no header, like the log file. Batches are
+available as JSON and NDJSON.
+
+## Metrics of the service in the Prometheus text format
+
+Endpoint: /metrics
+
+## Health and readiness
+
+Endpoints: /healthz, /readyz
+
+Both give the loading progress of the log, /readyz answers with a 503 until it has been loaded. Other endpoints are
+unavailable (503) meanwhile.
+
+## Caching
+
+Responses of versioned GET endpoints carry an ETag, which changes when the log is reloaded, and can be revalidated
+with If-None-Match.
+
+## Errors
+
+Errors are described by application/problem+json bodies (RFC 7807). Invalid parameters are listed in invalid-params.
+Requests asking for too many queries are answered with a 400, requests that would scan too many occurrences with a 429
+and requests that take too long with a 503";
+
+fn handle_default() -> HandlerResult {
+    Ok((CONTENT_TYPE_TEXT, Content::Full(DEFAULT_CONTENT.to_string()), StatusCode::OK))
 }
 
-fn handle_count(solver: &Solver, _version: u32, time_range: TimeRange, distinct: Option<()>) -> (ContentType, String, StatusCode) {
-    let count = match distinct {
-        Some(_) => solver.query_distinct_count(&time_range.from, &time_range.to),
-        _ => solver.query_count(&time_range.from, &time_range.to)
+fn handle_metrics(solver: Option<&Solver>, context: &Context) -> HandlerResult {
+    let metrics = context.metrics.render(solver, context.pool.jobs());
+    Ok((CONTENT_TYPE_METRICS, Content::Full(metrics), StatusCode::OK))
+}
+
+/// The service is alive as soon as it listens, even while the log is being loaded
+fn handle_healthz(state: &State) -> HandlerResult {
+    Ok((CONTENT_TYPE_JSON, Content::Full(status_json(state).to_string()), StatusCode::OK))
+}
+
+/// The service is ready once the log has been loaded
+fn handle_readyz(state: &State) -> HandlerResult {
+    let status = match state.solver() {
+        Some(_) => StatusCode::OK,
+        None => StatusCode::SERVICE_UNAVAILABLE
     };
-    let body = json!({
-        "from": time_range.from.to_string(),
-        "to": time_range.to.to_string(),
+    Ok((CONTENT_TYPE_JSON, Content::Full(status_json(state).to_string()), status))
+}
+
+fn status_json(state: &State) -> serde_json::Value {
+    let progress = state.progress();
+    match state.solver() {
+        Some(solver) => json!({
+            "status": "ready",
+            "rows": solver.occurrences()
+        }),
+        None => json!({
+            "status": "loading",
+            "progress": progress.percent(),
+            "rows": progress.rows()
+        })
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn handle_count(scope: &Scope, api: &'static Api, time_range: Param<TimeRange>, distinct: Option<()>, stats: Option<()>,
+                filter: Option<String>, ignore_case: Option<()>, regex: Option<()>) -> OutputResult {
+    let time_range = time_range.get("time_range")?;
+    let filter = query_filter(filter, ignore_case.is_some(), regex.is_some())?;
+    count_output(scope, api, &time_range, distinct.is_some(), stats.is_some(), filter.as_ref())
+}
+
+fn handle_popular(scope: &Scope, api: &'static Api, time_range: Param<TimeRange>, size: Option<Param<usize>>,
+                  filter: Option<String>, ignore_case: Option<()>, regex: Option<()>) -> OutputResult {
+    let time_range = time_range.get("time_range")?;
+    let size = Param::get_optional(size, "size")?;
+    let filter = query_filter(filter, ignore_case.is_some(), regex.is_some())?;
+    popular_output(scope, api, &time_range, size, filter.as_ref())
+}
+
+/// Compile the filter of a request, if any. `ignore_case` and `regex` are ignored without one.
+fn query_filter(filter: Option<String>, ignore_case: bool, regex: bool) -> Result<Option<QueryFilter>, Problem> {
+    let filter = match filter {
+        Some(filter) => filter,
+        None => return Ok(None)
+    };
+    let pattern = match percent_decode(&filter) {
+        Some(decoded) => decoded,
+        None => return Err(Problem::invalid_param("filter", &filter, "expected a percent-encoded string".to_string()))
+    };
+    QueryFilter::new(&pattern, ignore_case, regex).map(Some).map_err(|error| {
+        Problem::invalid_param("filter", &filter, format!("expected a regular expression: {}", error))
+    })
+}
+
+/// Count the queries of a time range, giving the dates of the first and last ones with `stats`.
+/// Only the queries matching `filter` are counted, which requires scanning the range.
+fn count_output(scope: &Scope, api: &'static Api, time_range: &TimeRange, distinct: bool, stats: bool,
+                filter: Option<&QueryFilter>) -> OutputResult {
+    let (solver, from, to) = (scope.solver, time_range.from, time_range.to);
+    if stats && filter.is_some() {
+        return Err(Problem::new(StatusCode::BAD_REQUEST, "Filtered counts cannot give stats".to_string()));
+    }
+    let count = scope.cache.count((solver.generation(), from, to, distinct, filter.cloned()), || match (distinct, filter) {
+        (true, filter) => {
+            scope.check_cost(&[time_range])?;
+            Ok(solver.query_distinct_count(&from, &to, filter, &scope.deadline)?)
+        },
+        (false, Some(filter)) => {
+            scope.check_cost(&[time_range])?;
+            Ok(solver.query_filtered_count(&from, &to, filter, &scope.deadline)?)
+        },
+        (false, None) => Ok::<_, Problem>(solver.query_count(&from, &to))
+    })?;
+    let mut count_json = json!({
+        "from": (api.format_date)(&time_range.from),
+        "to": (api.format_date)(&time_range.to),
         "count": count
-    }).to_string();
-    (CONTENT_TYPE_JSON, body, StatusCode::OK)
+    });
+    if let Some(filter) = filter {
+        count_json["filter"] = json!(filter.pattern());
+    }
+    if !stats {
+        return Ok(Output::single(count_json, &["from", "to", "count"]));
+    }
+
+    // Both dates are found by the same query of the segment tree, in O(log N) operations
+    let date_stats = solver.query_stats(&from, &to);
+    count_json["first"] = json!(date_stats.first.0.as_ref().map(api.format_date));
+    count_json["last"] = json!(date_stats.last.0.as_ref().map(api.format_date));
+    Ok(Output::single(count_json, &["from", "to", "count", "first", "last"]))
 }
 
-fn handle_popular(solver: &Solver, _version: u32, time_range: TimeRange, size: Option<usize>) -> (ContentType, String, StatusCode) {
+fn popular_output(scope: &Scope, api: &'static Api, time_range: &TimeRange, size: Option<usize>,
+                  filter: Option<&QueryFilter>) -> OutputResult {
     const DEFAULT_SIZE: usize = 10;
-    let k_queries = solver.query_k_count(&time_range.from, &time_range.to, size.unwrap_or(DEFAULT_SIZE));
-    let k_queries_json: serde_json::Value = k_queries.iter()
-                                                     .map(|&(ref query, ref count)| json!({
-                                                         "query": query,
-                                                         "count": count
-                                                     })).collect();
-    let body = json!({
-        "from": time_range.from.to_string(),
-        "to": time_range.to.to_string(),
-        "queries": k_queries_json
-    }).to_string();
-    (CONTENT_TYPE_JSON, body, StatusCode::OK)
+    let (solver, from, to) = (scope.solver, time_range.from, time_range.to);
+    let size = scope.check_size(size.unwrap_or(DEFAULT_SIZE))?;
+    let mut k_queries = scope.cache.popular((solver.generation(), from, to, size, filter.cloned()), || {
+        scope.check_cost(&[time_range])?;
+        Ok::<_, Problem>(solver.query_k_count(&from, &to, size, filter, &scope.deadline)?)
+    })?;
+    if api.sort_popular {
+        k_queries.sort_by(|(query_a, count_a), (query_b, count_b)| count_b.cmp(count_a).then_with(|| query_a.cmp(query_b)));
+    }
+    let k_queries_json = k_queries.into_iter()
+                                  .map(|(query, count)| json!({
+                                      "query": query,
+                                      "count": count
+                                  }));
+    let mut document = json!({
+        "from": (api.format_date)(&time_range.from),
+        "to": (api.format_date)(&time_range.to)
+    });
+    if let Some(filter) = filter {
+        document["filter"] = json!(filter.pattern());
+    }
+    Ok(Output::field(document, "queries", Box::new(k_queries_json), &["query", "count"]))
+}
+
+/// Run every operation of a batch against the same solver, the failure of an operation does
+/// not fail the whole batch
+fn handle_batch(scope: &Scope, api: &'static Api, body: &[u8]) -> OutputResult {
+    let operations: Vec<serde_json::Value> = serde_json::from_slice(body).map_err(|error| {
+        Problem::new(StatusCode::BAD_REQUEST, format!("Invalid batch: {}", error))
+    })?;
+
+    if operations.len() > MAX_BATCH_OPERATIONS {
+        return Err(Problem::too_large(format!("Batches are limited to {} operations", MAX_BATCH_OPERATIONS)));
+    }
+
+    let results: Vec<serde_json::Value> = operations.iter()
+                                                    .map(|operation| {
+                                                        batch_operation_json(scope, api, operation).unwrap_or_else(|error| json!({
+                                                            "error": error
+                                                        }))
+                                                    }).collect();
+    Ok(Output::array(Box::new(results.into_iter()), &[]))
+}
+
+fn batch_operation_json(scope: &Scope, api: &'static Api, operation: &serde_json::Value) -> Result<serde_json::Value, String> {
+    let time_range = operation["range"].as_str()
+                                       .ok_or_else(|| "missing range".to_string())
+                                       .and_then(|range| TimeRange::from_str(range).map_err(|_| format!("invalid range: {}", range)))?;
+
+    let filter = match operation["filter"].as_str() {
+        Some(pattern) => {
+            let flag = |name: &str| operation[name].as_bool().unwrap_or(false);
+            let filter = QueryFilter::new(pattern, flag("ignore_case"), flag("regex")).map_err(|error| format!("invalid filter: {}", error))?;
+            Some(filter)
+        },
+        None => None
+    };
+
+    match operation["op"].as_str() {
+        Some("count") => count_output(scope, api, &time_range, false, false, filter.as_ref()).map(Output::to_json).map_err(|problem| problem.detail),
+        Some("distinct") => count_output(scope, api, &time_range, true, false, filter.as_ref()).map(Output::to_json).map_err(|problem| problem.detail),
+        Some("popular") => {
+            let size = match operation.get("size") {
+                None => None,
+                Some(size) => Some(size.as_u64().ok_or_else(|| "invalid size".to_string())? as usize)
+            };
+            popular_output(scope, api, &time_range, size, filter.as_ref()).map(Output::to_json).map_err(|problem| problem.detail)
+        },
+        Some(op) => Err(format!("unknown op: {}", op)),
+        None => Err("missing op".to_string())
+    }
+}
+
+fn handle_trending(scope: &Scope, api: &'static Api, baseline: Param<TimeRange>, target: Param<TimeRange>,
+                   size: Option<Param<usize>>, min_support: Option<Param<usize>>,
+                   score: Option<Param<TrendScore>>) -> OutputResult {
+    const DEFAULT_SIZE: usize = 10;
+    const DEFAULT_MIN_SUPPORT: usize = 1;
+    let baseline = baseline.get("baseline")?;
+    let target = target.get("target")?;
+    let size = Param::get_optional(size, "size")?;
+    let min_support = Param::get_optional(min_support, "min_support")?;
+    let score = Param::get_optional(score, "score")?.unwrap_or(TrendScore::Absolute);
+    let size = scope.check_size(size.unwrap_or(DEFAULT_SIZE))?;
+    scope.check_cost(&[&baseline, &target])?;
+    let trends = scope.solver.query_trending((&baseline.from, &baseline.to), (&target.from, &target.to), size,
+                                             min_support.unwrap_or(DEFAULT_MIN_SUPPORT), score, &scope.deadline)?;
+    let trends_json = trends.into_iter()
+                            .map(|trend| json!({
+                                "query": trend.query,
+                                "baseline": trend.baseline,
+                                "target": trend.target,
+                                "growth": trend.growth
+                            }));
+    let document = json!({
+        "baseline": {
+            "from": (api.format_date)(&baseline.from),
+            "to": (api.format_date)(&baseline.to)
+        },
+        "target": {
+            "from": (api.format_date)(&target.from),
+            "to": (api.format_date)(&target.to)
+        },
+        "score": score.to_string()
+    });
+    Ok(Output::field(document, "queries", Box::new(trends_json), &["query", "baseline", "target", "growth"]))
+}
+
+fn handle_anomalies(scope: &Scope, api: &'static Api, time_range: Param<TimeRange>,
+                    granularity: Option<Param<Granularity>>, window: Option<Param<usize>>,
+                    threshold: Option<Param<f64>>) -> OutputResult {
+    const DEFAULT_WINDOW: usize = 60;
+    const DEFAULT_THRESHOLD: f64 = 3.0;
+    let time_range = time_range.get("time_range")?;
+    let granularity = Param::get_optional(granularity, "granularity")?;
+    let window = Param::get_optional(window, "window")?;
+    let threshold = Param::get_optional(threshold, "threshold")?;
+    let anomalies = scope.solver.query_anomalies(&time_range.from, &time_range.to, granularity.unwrap_or(Granularity::Minute),
+                                                 window.unwrap_or(DEFAULT_WINDOW), threshold.unwrap_or(DEFAULT_THRESHOLD),
+                                                 &scope.deadline)?;
+    let anomalies_json = anomalies.into_iter()
+                                  .map(move |anomaly| json!({
+                                      "from": (api.format_date)(&anomaly.from),
+                                      "to": (api.format_date)(&anomaly.to),
+                                      "expected": anomaly.expected,
+                                      "observed": anomaly.observed,
+                                      "z_score": anomaly.z_score
+                                  }));
+    let document = json!({
+        "from": (api.format_date)(&time_range.from),
+        "to": (api.format_date)(&time_range.to)
+    });
+    Ok(Output::field(document, "anomalies", Box::new(anomalies_json), &["from", "to", "expected", "observed", "z_score"]))
+}
+
+fn handle_completions(scope: &Scope, api: &'static Api, time_range: Param<TimeRange>, prefix: Option<String>,
+                      size: Option<Param<usize>>) -> OutputResult {
+    const DEFAULT_SIZE: usize = 10;
+    let time_range = time_range.get("time_range")?;
+    let prefix = match prefix {
+        Some(prefix) => match percent_decode(&prefix) {
+            Some(decoded) => decoded,
+            None => return Err(Problem::invalid_param("prefix", &prefix, "expected a percent-encoded string".to_string()))
+        },
+        None => String::new()
+    };
+    let size = scope.check_size(Param::get_optional(size, "size")?.unwrap_or(DEFAULT_SIZE))?;
+
+    // Ranges covering every row are answered from the trie without scanning
+    if scope.solver.query_count(&time_range.from, &time_range.to) < scope.solver.occurrences() {
+        scope.check_cost(&[&time_range])?;
+    }
+
+    let mut completions = scope.solver.query_completions(&prefix, &time_range.from, &time_range.to, size, &scope.deadline)?;
+    if api.sort_popular {
+        completions.sort_by(|(query_a, count_a), (query_b, count_b)| count_b.cmp(count_a).then_with(|| query_a.cmp(query_b)));
+    }
+    let completions_json = completions.into_iter()
+                                      .map(|(query, count)| json!({
+                                          "query": query,
+                                          "count": count
+                                      }));
+    let document = json!({
+        "from": (api.format_date)(&time_range.from),
+        "to": (api.format_date)(&time_range.to),
+        "prefix": prefix
+    });
+    Ok(Output::field(document, "queries", Box::new(completions_json), &["query", "count"]))
+}
+
+fn handle_variants(solver: &Arc<Solver>, query: String) -> OutputResult {
+    let decoded = match percent_decode(&query) {
+        Some(decoded) => decoded,
+        None => return Err(Problem::invalid_param("query", &query, "expected a percent-encoded string".to_string()))
+    };
+    let (canonical, variants) = match solver.query_variants(&decoded) {
+        Some(found) => found,
+        None => return Err(Problem::new(StatusCode::NOT_FOUND, format!("The query '{}' is not in the log", decoded)))
+    };
+
+    let variants_json = variants.into_iter()
+                                .map(|(query, count)| json!({
+                                    "query": query,
+                                    "count": count
+                                }));
+    Ok(Output::field(json!({ "query": canonical }), "variants", Box::new(variants_json), &["query", "count"]))
+}
+
+fn handle_logs(solver: &Arc<Solver>, api: &'static Api, time_range: Param<TimeRange>, contains: Option<String>) -> OutputResult {
+    let time_range = time_range.get("time_range")?;
+    let contains = match contains {
+        Some(contains) => match percent_decode(&contains) {
+            Some(decoded) => Some(decoded),
+            None => {
+                return Err(Problem::invalid_param("contains", &contains, "expected a percent-encoded string".to_string()));
+            }
+        },
+        None => None
+    };
+
+    let rows = Solver::rows(solver, &time_range.from, &time_range.to)
+                      .filter(move |(_, query)| contains.as_ref().is_none_or(|contains| query.contains(contains.as_str())))
+                      .map(move |(date, query)| json!({
+                          "date": (api.format_date)(&date),
+                          "query": query
+                      }));
+    Ok(Output::array(Box::new(rows), &["date", "query"]))
 }
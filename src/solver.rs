@@ -1,193 +1,677 @@
-use std::io::{ Result };
-use std::io::prelude::*;
+use std::io;
 
 use std::fs::File;
-use std::io::BufReader;
 
-use std::collections::hash_map::{ DefaultHasher, HashMap };
+use std::collections::HashMap;
 use std::collections::HashSet;
-use std::hash::{ Hash, Hasher };
+use std::cmp::Ordering;
+use std::str::FromStr;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU64, Ordering as AtomicOrdering };
+use std::time::Instant;
+use std::thread;
 
 use tree::GenericTree;
 use tree::range_tree::RangeTree;
+use tree::RangeAggregate;
 use tree::segment_tree::SegmentTree;
-use tree::heap::MinHeap;
-use monoid::Monoid;
+use tree::fenwick_tree::FenwickTree;
+use tree::sparse_table::SparseTable;
+use tree::heap::BoundedTopK;
+use tree::trie::Trie;
+use monoid::{ Monoid, Min, Max };
+use time_range::Granularity;
+use state::Progress;
+use deadline::{ Deadline, Expired };
+use ingest;
+use ingest::Variants;
+use normalize::Normalization;
+use filter::QueryFilter;
 
 use itertools::Itertools;
 
-use chrono::NaiveDateTime;
+use chrono::{ Duration, NaiveDateTime };
 
 type Date = NaiveDateTime;
 type QueryId = u64;
 type DateId = usize;
 
+/// Scans check their deadline every this many dates
+const DEADLINE_CHECK_DATES: usize = 1024;
+
+/// Number of indexes built so far, giving each one its generation
+static GENERATIONS: AtomicU64 = AtomicU64::new(0);
+
 #[derive(Clone)]
 pub struct Solver {
     queries: HashMap<QueryId, String>,      // Storage of queries
-    dates: HashMap<Date, DateId>,           // Storage of dates
+    variants: Variants,                     // Raw queries merged into each query by normalization
+    query_trie: Arc<Trie<QueryId>>,         // Queries by prefix, weighted by their number of rows
+    date_list: Vec<Date>,                   // Dates indexed by their id
     grouped_queries: Vec<Vec<QueryId>>,
-    date_range_tree: RangeTree<Date>,       // Range tree of Date for finding correct ranges in log(N)
-    segment_tree: SegmentTree<usize>        // Segment tree for finding number of queries in a range in log(N)
+    date_range_tree: RangeTree<Date>,       // Range tree of Date for finding the ids of ranges in log(N)
+    date_stats: Arc<dyn RangeAggregate<DateStats> + Send + Sync>,  // Aggregates of the rows of ranges of dates
+    occurrences: usize,                     // Number of rows of the log
+    generation: u64,                        // Identifier of this build of the index
+    load_stats: LoadStats,
+    options: SolverOptions
 }
 
-impl Solver {
-    /// Build data structures to answer queries efficiently
-    pub fn new(tsv_filename: &str) -> Result<Self> {
-        const TSV_SEP: char = '\t';
-
-        let file = File::open(tsv_filename)?;
-        let reader = BufReader::new(file);
-        
-        // Lazy parsing of the TSV file into (Date, String) tuples
-        let tsv_fields = reader.lines().map(|maybe_line| {
-            let line = maybe_line.unwrap();
-            let fields: Vec<&str> = line.split(TSV_SEP).collect();
-            (Date::parse_from_str(fields[0], "%F %T").unwrap(), String::from(fields[1]))
-        });
+/// How the solver answers queries
+#[derive(Clone, Copy)]
+pub struct SolverOptions {
+    pub scan_threads: usize,                // Threads a range scan is split between
+    pub scan_threshold: usize,              // Ranges with fewer occurrences are scanned by a single thread
+    pub load_threads: usize,                // Threads parsing the log
+    pub range_index: RangeIndex,            // Structures aggregating ranges of dates
+    pub normalization: Normalization        // Steps mapping raw queries to canonical ones
+}
 
-        // Hash queries and keep them in a hashmap
-        // We also maintain a vector of (Date, QueryId) for later
-        // We have to process N queries
-        let mut queries: HashMap<QueryId, String> = HashMap::new();
-        let mut entries: Vec<(Date, QueryId)> = Vec::new();
+/// Structures finding the aggregates of ranges of dates
+#[derive(Clone, Copy)]
+pub enum RangeIndex {
+    /// A segment tree of all the aggregates: O(log N) operations per range
+    Segment,
+    /// A Fenwick tree of counts and a sparse table of dates: O(log N) operations per range with
+    /// fewer cache misses for counts, O(1) for dates, O(N log N) storage
+    FenwickSparse
+}
 
-        for (date, query) in tsv_fields {
-            let mut hasher = DefaultHasher::new();
-            query.hash(&mut hasher);
-            let query_hash = hasher.finish();
+impl FromStr for RangeIndex {
+    type Err = ();
 
-            queries.entry(query_hash).or_insert(query);
-            entries.push((date, query_hash));
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        match data {
+            "segment" => Ok(RangeIndex::Segment),
+            "fenwick-sparse" => Ok(RangeIndex::FenwickSparse),
+            _ => Err(())
         }
+    }
+}
 
-        // Sort entries, group and index them by date
-        // Sorting: O(N log N)
-        // Grouping: O(N)
-        entries.sort();
-        let mut grouped_queries: Vec<(Date, Vec<QueryId>)> = Vec::new();
-        for (date, query_group) in &entries.into_iter().group_by(|&entry| entry.0) {
-            let queries: Vec<QueryId> = query_group.into_iter().map(|(_, query)| query).collect();
-            grouped_queries.push((date, queries));
+monoid! {
+    /// Aggregates of the rows of a range of dates, all found by the same query of the segment tree
+    #[derive(Clone, Copy)]
+    pub struct DateStats {
+        pub occurrences: usize,             // Number of rows
+        pub first: Min<Date>,               // Date of the first row
+        pub last: Max<Date>                 // Date of the last row
+    }
+}
+
+/// Counts of rows in a Fenwick tree and their dates in a sparse table, answering the same
+/// aggregates as a segment tree of `DateStats`
+struct FenwickSparse {
+    occurrences: FenwickTree<usize>,
+    dates: SparseTable<(Min<Date>, Max<Date>)>
+}
+
+impl FenwickSparse {
+    fn with_leaves(leaves: &[DateStats]) -> Self {
+        let occurrences: Vec<usize> = leaves.iter().map(|stats| stats.occurrences).collect();
+        let dates: Vec<(Min<Date>, Max<Date>)> = leaves.iter().map(|stats| (stats.first, stats.last)).collect();
+        FenwickSparse {
+            occurrences: FenwickTree::with_leaves(&occurrences),
+            dates: SparseTable::with_leaves(&dates)
         }
+    }
+}
 
-        // Build the mapping of Date -> DateId
-        let mut date_map = HashMap::with_capacity(grouped_queries.len());
-        for (date_id, &(date, _)) in grouped_queries.iter().enumerate() {
-            date_map.insert(date.clone(), date_id);
+impl RangeAggregate<DateStats> for FenwickSparse {
+    fn aggregate(&self, left: usize, right: usize) -> DateStats {
+        let (first, last) = self.dates.aggregate(left, right);
+        DateStats {
+            occurrences: self.occurrences.aggregate(left, right),
+            first,
+            last
         }
+    }
+}
 
-        // Collect leaves of the range tree of dates
-        let range_tree_leaves: Vec<Date> = grouped_queries.iter().map(|&(date, _)| date).collect();
+/// Statistics of the loading of a log file
+#[derive(Clone, Copy)]
+pub struct LoadStats {
+    pub duration: f64,                      // Time spent building the data structures, in seconds
+    pub rejected_rows: usize                // Rows that could not be parsed and were skipped
+}
+
+impl Solver {
+    /// Build data structures to answer queries efficiently, reporting the bytes and rows read
+    /// to `progress`
+    pub fn new(tsv_filename: &str, options: SolverOptions, progress: &Progress) -> io::Result<Self> {
+        let start = Instant::now();
+        let file = File::open(tsv_filename)?;
+        progress.start(file.metadata()?.len());
 
-        // Collect leaves of the segment tree of number of queries
-        let seg_tree_leaves: Vec<usize> = grouped_queries.iter().map(|&(_, ref v)| v.len()).collect();
+        // Hash queries and keep them in a hashmap
+        // We also get sorted runs of (Date, QueryId) for later
+        // We have to process N queries, split between the loading threads
+        let parsed = ingest::parse(progress.reader(file), options.load_threads, options.normalization, progress)?;
+        let queries = parsed.queries;
+        let variants = parsed.variants;
+        let rejected_rows = parsed.rejected_rows;
+
+        // Merge runs, group and index entries by date
+        // Merging: O(N log T) for T runs
+        // Grouping: O(N)
+        let mut date_list: Vec<Date> = Vec::new();
+        let mut grouped_queries: Vec<Vec<QueryId>> = Vec::new();
+        for (date, query_group) in &ingest::merge_runs(parsed.runs).group_by(|&entry| entry.0) {
+            date_list.push(date);
+            grouped_queries.push(query_group.map(|(_, query)| query).collect());
+        }
+
+        // Collect leaves of the segment tree of number of queries and dates
+        let seg_tree_leaves: Vec<DateStats> = date_list.iter()
+                                                       .zip(&grouped_queries)
+                                                       .map(|(date, queries)| DateStats {
+                                                           occurrences: queries.len(),
+                                                           first: Min(Some(*date)),
+                                                           last: Max(Some(*date))
+                                                       })
+                                                       .collect();
+        let date_stats: Arc<dyn RangeAggregate<DateStats> + Send + Sync> = match options.range_index {
+            RangeIndex::Segment => Arc::new(SegmentTree::<DateStats>::with_leaves(&seg_tree_leaves)),
+            RangeIndex::FenwickSparse => Arc::new(FenwickSparse::with_leaves(&seg_tree_leaves))
+        };
+
+        // Index queries by prefix, weighted by their number of rows
+        // Counting: O(N)
+        let mut totals: HashMap<QueryId, usize> = HashMap::with_capacity(queries.len());
+        for query_id in grouped_queries.iter().flat_map(|queries| queries.iter()) {
+            *totals.entry(*query_id).or_insert(0) += 1;
+        }
+        let query_trie = Trie::with_entries(queries.iter()
+                                                   .map(|(query_id, query)| (query.as_str(), *query_id, totals.get(query_id).cloned().unwrap_or(0)))
+                                                   .collect());
 
         Ok(Solver {
-            queries: queries,
-            dates: date_map,
-            grouped_queries: grouped_queries.iter().map(|&(_, ref v)| v.clone()).collect(),
-            date_range_tree: RangeTree::with_leaves(&range_tree_leaves),
-            segment_tree: SegmentTree::with_leaves(&seg_tree_leaves)
+            queries,
+            query_trie: Arc::new(query_trie),
+            variants,
+            date_range_tree: RangeTree::with_leaves(&date_list),
+            date_list,
+            grouped_queries,
+            occurrences: seg_tree_leaves.iter().map(|stats| stats.occurrences).sum(),
+            date_stats,
+            generation: GENERATIONS.fetch_add(1, AtomicOrdering::SeqCst) + 1,
+            load_stats: LoadStats {
+                duration: start.elapsed().as_secs_f64(),
+                rejected_rows
+            },
+            options
         })
     }
 
+    pub fn distinct_queries(&self) -> usize {
+        self.queries.len()
+    }
+
+    pub fn distinct_dates(&self) -> usize {
+        self.date_list.len()
+    }
+
+    pub fn occurrences(&self) -> usize {
+        self.occurrences
+    }
+
+    /// Identifier of this build of the index, a reloaded index has a greater one
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn load_stats(&self) -> LoadStats {
+        self.load_stats
+    }
+
+    /// Dates are identified by their index in the sorted dates, which is also their leaf in the
+    /// range tree
     fn find_date_range_ids(&self, from: &Date, to: &Date) -> Option<(DateId, DateId)> {
-        let maybe_range = self.date_range_tree.largest_range(from, to);
-        match maybe_range {
-            None => None,
-
-            Some((from, to)) => {
-                self.dates.get(&from).and_then(|from_id| {
-                    self.dates.get(&to).and_then(|to_id| {
-                        Some((*from_id, *to_id))
-                    })
-                })
-            }
+        self.date_range_tree.largest_range(from, to)
+    }
+
+    /// Iterate over the rows of the log in a range of dates, in chronological order. Rows of the
+    /// same date are ordered by query identifier since their original order is not kept.
+    pub fn rows(solver: &Arc<Solver>, from: &Date, to: &Date) -> Rows {
+        let (date_id, end) = match solver.find_date_range_ids(from, to) {
+            Some((from_id, to_id)) => (from_id, to_id + 1),
+            None => (0, 0)
+        };
+        Rows {
+            solver: Arc::clone(solver),
+            date_id,
+            end,
+            index: 0,
+            remaining: solver.query_count(from, to)
         }
     }
 
     /// Query number of queries in a range
     pub fn query_count(&self, from: &Date, to: &Date) -> usize {
+        self.query_stats(from, to).occurrences
+    }
+
+    /// Query the number of queries in a range along with the dates of the first and last ones
+    pub fn query_stats(&self, from: &Date, to: &Date) -> DateStats {
         match self.find_date_range_ids(from, to) {
             Some((from_id, to_id)) => {
-                self.segment_tree.query(from_id, to_id)
+                self.date_stats.aggregate(from_id, to_id)
             },
 
-            _ => 0
+            _ => DateStats::m_empty()
         }
     }
 
-    /// Query number of distinct queries in a range
-    pub fn query_distinct_count(&self, from: &Date, to: &Date) -> usize {
+    /// Find the canonical form of a query and the raw queries merged into it when the log was
+    /// loaded, with their number of rows, most frequent first. There are no variants when queries
+    /// are not normalized.
+    pub fn query_variants(&self, query: &str) -> Option<(String, Vec<(String, usize)>)> {
+        let query_id = ingest::query_id(&self.options.normalization.apply(query));
+        let canonical = self.queries.get(&query_id)?;
+        let mut variants: Vec<(String, usize)> = self.variants.get(&query_id)
+                                                              .map(|variants| variants.iter()
+                                                                                      .map(|(variant, count)| (variant.clone(), *count))
+                                                                                      .collect())
+                                                              .unwrap_or_default();
+        variants.sort_by(|(query_a, count_a), (query_b, count_b)| count_b.cmp(count_a).then_with(|| query_a.cmp(query_b)));
+        Some((canonical.clone(), variants))
+    }
+
+    /// Query the number of queries matching `filter` in a range, unless `deadline` passes
+    pub fn query_filtered_count(&self, from: &Date, to: &Date, filter: &QueryFilter, deadline: &Deadline) -> Result<usize, Expired> {
         match self.find_date_range_ids(from, to) {
             Some((from_id, to_id)) => {
-                let mut query_set: HashSet<QueryId> = HashSet::new();
-                for date_id in from_id .. to_id + 1 {
-                    for query_id in self.grouped_queries[date_id].iter() {
-                        query_set.insert(*query_id);
-                    }
+                let among = self.matching_queries(filter);
+                if among.is_empty() {
+                    return Ok(0);
                 }
-                query_set.len()
+                self.scan(from_id, to_id, deadline, |count: &mut usize, queries| {
+                    *count += queries.iter()
+                                     .filter(|query_id| among.contains(query_id))
+                                     .count();
+                })
             },
 
-            _ => 0
+            _ => Ok(0)
         }
     }
 
-    /// Query k most frequent requests in a range
-    pub fn query_k_count(&self, from: &Date, to: &Date, k: usize) -> Vec<(String, usize)> {
+    /// Query number of distinct queries in a range, or of the ones matching `filter`, unless
+    /// `deadline` passes
+    pub fn query_distinct_count(&self, from: &Date, to: &Date, filter: Option<&QueryFilter>, deadline: &Deadline) -> Result<usize, Expired> {
         match self.find_date_range_ids(from, to) {
-            Some((from_id, to_id)) if k > 0 => {
-                let mut query_counts: HashMap<QueryId, usize> = HashMap::new();
-
-                // Count queries in the given range
-                for date_id in from_id .. to_id + 1 {
-                    for query_id in self.grouped_queries[date_id].iter() {
-                        let count = query_counts.entry(*query_id)
-                                                .or_insert(0);
-                        *count += 1;
+            Some((from_id, to_id)) => {
+                let among = filter.map(|filter| self.matching_queries(filter));
+                let query_set = self.scan(from_id, to_id, deadline, |query_set: &mut QuerySet, queries| {
+                    match among {
+                        Some(ref among) => query_set.0.extend(queries.iter().filter(|query_id| among.contains(query_id))),
+                        None => query_set.0.extend(queries)
                     }
-                }
+                })?;
+                Ok(query_set.0.len())
+            },
+
+            _ => Ok(0)
+        }
+    }
 
-                // To solve the problem we maintain a min-heap with at most the k most frequent queries
-                let mut solution = MinHeap::new();
+    /// Queries of the dictionary matching `filter`: each query is matched once, however many
+    /// times it occurs, and scans then only look its identifier up
+    fn matching_queries(&self, filter: &QueryFilter) -> HashSet<QueryId> {
+        self.queries.iter()
+                    .filter(|(_, query)| filter.matches(query))
+                    .map(|(query_id, _)| *query_id)
+                    .collect()
+    }
 
-                // 1. fill the min-heap with the first k elements.
-                let query_count_iter_k = query_counts.iter().take(k).map(|(query, count)| (count, query));
-                for query_count in query_count_iter_k {
-                    solution.insert(query_count);
+    /// Count occurrences of each query between two date ids (both included), or only of the
+    /// queries `among`, unless `deadline` passes
+    fn count_queries(&self, from_id: DateId, to_id: DateId, among: Option<&HashSet<QueryId>>,
+                     deadline: &Deadline) -> Result<HashMap<QueryId, usize>, Expired> {
+        let query_counts = self.scan(from_id, to_id, deadline, |query_counts: &mut QueryCounts, queries| {
+            for query_id in queries {
+                if among.is_some_and(|among| !among.contains(query_id)) {
+                    continue;
                 }
+                let count = query_counts.0.entry(*query_id)
+                                          .or_insert(0);
+                *count += 1;
+            }
+        })?;
+        Ok(query_counts.0)
+    }
 
-                // 2. for the remaining elements just look if their count is greater than the one
-                //    of the root of the min heap. If so, remove replace the root with this
-                //    element.
-                let query_count_iter_others = query_counts.iter().skip(k).map(|(query, count)| (count, query));
-                for query_count in query_count_iter_others {
-                    let head = solution.peek().unwrap().clone();
-                    if head < query_count {
-                        solution.extract();
-                        solution.insert(query_count);
-                    }
+    /// Fold the queries of the dates between two date ids (both included) into a monoid, unless
+    /// `deadline` passes. Large ranges are split into chunks of dates folded in parallel, then
+    /// partial results are appended in order.
+    fn scan<M, F>(&self, from_id: DateId, to_id: DateId, deadline: &Deadline, fold: F) -> Result<M, Expired>
+        where M: Monoid + Send, F: Fn(&mut M, &[QueryId]) + Sync {
+        let threads = self.options.scan_threads;
+        if threads <= 1 || self.date_stats.aggregate(from_id, to_id).occurrences < self.options.scan_threshold {
+            return self.scan_chunk(from_id, to_id + 1, deadline, &fold);
+        }
+
+        let chunk_size = (to_id + 1 - from_id).div_ceil(threads);
+        let partials: Vec<Result<M, Expired>> = thread::scope(|scope| {
+            let fold = &fold;
+            let handles: Vec<_> = (from_id .. to_id + 1).step_by(chunk_size)
+                                                         .map(|start| {
+                                                             let end = ::std::cmp::min(start + chunk_size, to_id + 1);
+                                                             scope.spawn(move || self.scan_chunk(start, end, deadline, fold))
+                                                         })
+                                                         .collect();
+            handles.into_iter()
+                   .map(|handle| handle.join().unwrap())
+                   .collect()
+        });
+        partials.into_iter()
+                .try_fold(M::m_empty(), |merged, partial| Ok(merged.m_concat(partial?)))
+    }
+
+    /// Fold the queries of the dates from `start` (included) to `end` (excluded)
+    fn scan_chunk<M: Monoid, F: Fn(&mut M, &[QueryId])>(&self, start: DateId, end: DateId, deadline: &Deadline,
+                                                        fold: &F) -> Result<M, Expired> {
+        let mut folded = M::m_empty();
+        for date_id in start .. end {
+            if (date_id - start).is_multiple_of(DEADLINE_CHECK_DATES) {
+                deadline.check()?;
+            }
+            fold(&mut folded, &self.grouped_queries[date_id]);
+        }
+        Ok(folded)
+    }
+
+    /// Count occurrences of each query in a range of dates
+    fn count_queries_in_range(&self, from: &Date, to: &Date, deadline: &Deadline) -> Result<HashMap<QueryId, usize>, Expired> {
+        match self.find_date_range_ids(from, to) {
+            Some((from_id, to_id)) => self.count_queries(from_id, to_id, None, deadline),
+            _ => Ok(HashMap::new())
+        }
+    }
+
+    /// Query k most frequent requests in a range, or among the ones matching `filter`, unless
+    /// `deadline` passes
+    pub fn query_k_count(&self, from: &Date, to: &Date, k: usize, filter: Option<&QueryFilter>,
+                         deadline: &Deadline) -> Result<Vec<(String, usize)>, Expired> {
+        match self.find_date_range_ids(from, to) {
+            Some((from_id, to_id)) if k > 0 => {
+                let among = filter.map(|filter| self.matching_queries(filter));
+                if among.as_ref().is_some_and(HashSet::is_empty) {
+                    return Ok(Vec::new());
                 }
+                let query_counts = self.count_queries(from_id, to_id, among.as_ref(), deadline)?;
+                Ok(self.most_frequent(&query_counts, k))
+            },
+
+            _ => Ok(Vec::new())
+        }
+    }
 
-                // Transfor the heap into a Vec
-                solution.into_iter()
-                        .map(|(count, query_id)| (self.queries.get(query_id).unwrap().clone(), count.clone()))
-                        .collect()
+    /// Query the k most frequent queries starting with `prefix` in a range, unless `deadline`
+    /// passes. The prefix is normalized like queries. Ranges covering every date are answered
+    /// from the weights of the trie of queries without scanning, other ones by only counting the
+    /// queries the trie gives.
+    pub fn query_completions(&self, prefix: &str, from: &Date, to: &Date, k: usize, deadline: &Deadline) -> Result<Vec<(String, usize)>, Expired> {
+        let prefix = self.options.normalization.apply(prefix);
+        match self.find_date_range_ids(from, to) {
+            Some((0, to_id)) if k > 0 && to_id + 1 == self.date_list.len() => {
+                Ok(self.query_trie.completions(&prefix, k)
+                                  .into_iter()
+                                  .map(|(query_id, count)| (self.queries[&query_id].clone(), count))
+                                  .collect())
             },
 
-            _ => Vec::new()
+            Some((from_id, to_id)) if k > 0 => {
+                let candidates: HashSet<QueryId> = self.query_trie.values(&prefix).into_iter().collect();
+                let among = match candidates.len() {
+                    0 => return Ok(Vec::new()),
+                    length if length == self.queries.len() => None,
+                    _ => Some(&candidates)
+                };
+                let query_counts = self.count_queries(from_id, to_id, among, deadline)?;
+                Ok(self.most_frequent(&query_counts, k))
+            },
+
+            _ => Ok(Vec::new())
+        }
+    }
+
+    /// Select the k most frequent queries, most frequent first
+    fn most_frequent(&self, query_counts: &HashMap<QueryId, usize>, k: usize) -> Vec<(String, usize)> {
+        // To solve the problem we maintain a min-heap with at most the k most frequent
+        // queries, whose root is replaced by the queries counted more than it
+        let mut solution = BoundedTopK::new(k);
+        for (query_id, count) in query_counts {
+            solution.push((*count, *query_id));
+        }
+
+        solution.into_sorted_vec()
+                .into_iter()
+                .map(|(count, query_id)| (self.queries[&query_id].clone(), count))
+                .collect()
+    }
+
+    /// Query the k queries whose frequency grew the most between a baseline and a target range.
+    /// Counts of the baseline are scaled to the duration of the target so that ranges of
+    /// different lengths can be compared. Queries seen less than `min_support` times in the
+    /// target range are ignored.
+    pub fn query_trending(&self, baseline: (&Date, &Date), target: (&Date, &Date), k: usize,
+                          min_support: usize, score: TrendScore, deadline: &Deadline) -> Result<Vec<Trend>, Expired> {
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+
+        let baseline_counts = self.count_queries_in_range(baseline.0, baseline.1, deadline)?;
+        let target_counts = self.count_queries_in_range(target.0, target.1, deadline)?;
+
+        // Ranges are inclusive so add one second to their durations
+        let duration = |from: &Date, to: &Date| (*to - *from).num_seconds() as f64 + 1.0;
+        let scale = duration(target.0, target.1) / duration(baseline.0, baseline.1);
+
+        // Highest growth first, ties are broken by the query itself to keep results stable
+        let mut trends = BoundedTopK::with_compare(k, |a: &Trend, b: &Trend| {
+            a.growth.partial_cmp(&b.growth)
+                    .unwrap_or(Ordering::Equal)
+                    .then_with(|| b.query.cmp(&a.query))
+        });
+        let candidates = target_counts.iter()
+            .filter(|&(_, &count)| count >= min_support)
+            .filter_map(|(query_id, &count)| {
+                let baseline_count = baseline_counts.get(query_id).cloned().unwrap_or(0);
+                let growth = score.growth(baseline_count as f64 * scale, count as f64);
+                if growth <= 0.0 {
+                    return None;
+                }
+                Some(Trend {
+                    query: self.queries[query_id].clone(),
+                    baseline: baseline_count,
+                    target: count,
+                    growth
+                })
+            });
+        for trend in candidates {
+            trends.push(trend);
         }
+        Ok(trends.into_sorted_vec())
     }
+
+    /// Find the buckets of a range whose volume of queries deviates from the rolling baseline made
+    /// of the `window` buckets preceding them. A bucket is anomalous when the absolute value of its
+    /// z-score is at least `threshold`.
+    pub fn query_anomalies(&self, from: &Date, to: &Date, granularity: Granularity, window: usize,
+                           threshold: f64, deadline: &Deadline) -> Result<Vec<Anomaly>, Expired> {
+        let step = granularity.duration();
+
+        // Start the history early enough for the first bucket to have a full baseline
+        let history_from = (0 .. window).fold(*from, |date, _| date.checked_sub_signed(step).unwrap_or(date));
+
+        // Count queries bucket by bucket, each count is a segment tree query
+        let mut buckets: Vec<(Date, Date, usize)> = Vec::new();
+        let mut bucket_from = history_from;
+        while bucket_from <= *to {
+            if buckets.len().is_multiple_of(DEADLINE_CHECK_DATES) {
+                deadline.check()?;
+            }
+            let bucket_to = ::std::cmp::min(bucket_from + step - Duration::seconds(1), *to);
+            buckets.push((bucket_from, bucket_to, self.query_count(&bucket_from, &bucket_to)));
+            bucket_from += step;
+        }
+
+        let mut anomalies = Vec::new();
+        for (i, &(bucket_from, bucket_to, observed)) in buckets.iter().enumerate() {
+            let baseline = &buckets[i.saturating_sub(window) .. i];
+            if bucket_from < *from || baseline.len() < 2 {
+                continue;
+            }
+
+            let n = baseline.len() as f64;
+            let mean = baseline.iter().map(|&(_, _, count)| count as f64).sum::<f64>() / n;
+            let variance = baseline.iter().map(|&(_, _, count)| (count as f64 - mean).powi(2)).sum::<f64>() / n;
+
+            // Counts are discrete: a flat baseline should not make any deviation infinitely anomalous
+            let std_dev = variance.sqrt().max(1.0);
+            let z_score = (observed as f64 - mean) / std_dev;
+            if z_score.abs() >= threshold {
+                anomalies.push(Anomaly {
+                    from: bucket_from,
+                    to: bucket_to,
+                    expected: mean,
+                    observed,
+                    z_score
+                });
+            }
+        }
+        Ok(anomalies)
+    }
+}
+
+/// Iterator over the (date, query) rows of a range of dates
+pub struct Rows {
+    solver: Arc<Solver>,
+    date_id: DateId,
+    end: DateId,
+    index: usize,
+    remaining: usize
 }
 
-impl Monoid for usize {
+impl Iterator for Rows {
+    type Item = (Date, String);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.date_id < self.end {
+            let date_queries = &self.solver.grouped_queries[self.date_id];
+            if let Some(query_id) = date_queries.get(self.index) {
+                self.index += 1;
+                self.remaining -= 1;
+                return Some((self.solver.date_list[self.date_id], self.solver.queries[query_id].clone()));
+            }
+            self.date_id += 1;
+            self.index = 0;
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+/// A bucket whose volume of queries deviates from its baseline
+pub struct Anomaly {
+    pub from: Date,
+    pub to: Date,
+    pub expected: f64,
+    pub observed: usize,
+    pub z_score: f64
+}
+
+/// How the growth of a query between two ranges is scored
+#[derive(Clone, Copy)]
+pub enum TrendScore {
+    /// Difference between the target count and the expected count
+    Absolute,
+    /// Difference between the target count and the expected count, relative to the expected count
+    Relative
+}
+
+impl TrendScore {
+    fn growth(self, expected: f64, observed: f64) -> f64 {
+        match self {
+            TrendScore::Absolute => observed - expected,
+            // Smooth the expected count so that new queries do not grow infinitely
+            TrendScore::Relative => (observed - expected) / (expected + 1.0)
+        }
+    }
+}
+
+impl FromStr for TrendScore {
+    type Err = ();
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        match data {
+            "absolute" => Ok(TrendScore::Absolute),
+            "relative" => Ok(TrendScore::Relative),
+            _ => Err(())
+        }
+    }
+}
+
+impl fmt::Display for TrendScore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TrendScore::Absolute => write!(f, "absolute"),
+            TrendScore::Relative => write!(f, "relative")
+        }
+    }
+}
+
+/// A query and its counts in the baseline and target ranges
+pub struct Trend {
+    pub query: String,
+    pub baseline: usize,
+    pub target: usize,
+    pub growth: f64
+}
+
+/// Occurrences of each query in a range, appended by summing counts
+struct QueryCounts(HashMap<QueryId, usize>);
+
+impl Monoid for QueryCounts {
+    fn m_empty() -> Self {
+        QueryCounts(HashMap::new())
+    }
+
+    fn m_append(&self, other: &Self) -> Self {
+        QueryCounts(self.0.clone()).m_concat(QueryCounts(other.0.clone()))
+    }
+
+    /// Add the counts of the smallest map to the largest one
+    fn m_concat(self, other: Self) -> Self {
+        let (mut largest, smallest) = if self.0.len() >= other.0.len() { (self, other) } else { (other, self) };
+        for (query_id, count) in smallest.0 {
+            *largest.0.entry(query_id).or_insert(0) += count;
+        }
+        largest
+    }
+}
+
+/// Queries seen in a range, appended by union
+struct QuerySet(HashSet<QueryId>);
+
+impl Monoid for QuerySet {
     fn m_empty() -> Self {
-        0
+        QuerySet(HashSet::new())
     }
 
     fn m_append(&self, other: &Self) -> Self {
-        self + other
+        QuerySet(self.0.union(&other.0).cloned().collect())
+    }
+
+    /// Insert the queries of the smallest set into the largest one
+    fn m_concat(self, other: Self) -> Self {
+        let (mut largest, smallest) = if self.0.len() >= other.0.len() { (self, other) } else { (other, self) };
+        largest.0.extend(smallest.0);
+        largest
     }
 }
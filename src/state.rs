@@ -0,0 +1,107 @@
+use std::io::{ self, Read };
+use std::sync::{ Arc, RwLock };
+use std::sync::atomic::{ AtomicBool, AtomicU64, AtomicUsize, Ordering };
+
+use solver::Solver;
+use cache::ResultCache;
+
+/// Progress of the loading of a log file, updated by the loader while requests read it
+#[derive(Default)]
+pub struct Progress {
+    total_bytes: AtomicU64,
+    read_bytes: AtomicU64,
+    rows: AtomicUsize
+}
+
+impl Progress {
+    /// Start tracking the loading of a file of `total_bytes`
+    pub fn start(&self, total_bytes: u64) {
+        self.total_bytes.store(total_bytes, Ordering::Relaxed);
+        self.read_bytes.store(0, Ordering::Relaxed);
+        self.rows.store(0, Ordering::Relaxed);
+    }
+
+    pub fn add_rows(&self, rows: usize) {
+        self.rows.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows.load(Ordering::Relaxed)
+    }
+
+    /// Percentage of the bytes of the file read so far
+    pub fn percent(&self) -> f64 {
+        match self.total_bytes.load(Ordering::Relaxed) {
+            0 => 0.0,
+            total => 100.0 * self.read_bytes.load(Ordering::Relaxed) as f64 / total as f64
+        }
+    }
+
+    /// Count the bytes read from `reader`
+    pub fn reader<'a, R: Read>(&'a self, reader: R) -> ProgressReader<'a, R> {
+        ProgressReader {
+            reader,
+            progress: self
+        }
+    }
+}
+
+/// Reader reporting the bytes it reads to a `Progress`
+pub struct ProgressReader<'a, R> {
+    reader: R,
+    progress: &'a Progress
+}
+
+impl<'a, R: Read> Read for ProgressReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.progress.read_bytes.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+/// State shared between requests: the solver is absent until the log has been loaded
+pub struct State {
+    solver: RwLock<Option<Arc<Solver>>>,
+    progress: Progress,
+    loading: AtomicBool,
+    cache: ResultCache
+}
+
+impl State {
+    /// State caching up to `cache_entries` results of each kind
+    pub fn new(cache_entries: usize) -> Self {
+        State {
+            solver: RwLock::new(None),
+            progress: Progress::default(),
+            loading: AtomicBool::new(false),
+            cache: ResultCache::new(cache_entries)
+        }
+    }
+
+    /// Current solver, requests keep the one they started with even if another is swapped in
+    pub fn solver(&self) -> Option<Arc<Solver>> {
+        self.solver.read().unwrap().clone()
+    }
+
+    pub fn set_solver(&self, solver: Solver) {
+        *self.solver.write().unwrap() = Some(Arc::new(solver));
+    }
+
+    /// Mark the log as being loaded, fails if it already is
+    pub fn start_loading(&self) -> bool {
+        self.loading.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_ok()
+    }
+
+    pub fn finish_loading(&self) {
+        self.loading.store(false, Ordering::SeqCst);
+    }
+
+    pub fn cache(&self) -> &ResultCache {
+        &self.cache
+    }
+
+    pub fn progress(&self) -> &Progress {
+        &self.progress
+    }
+}
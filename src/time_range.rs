@@ -1,6 +1,6 @@
 use std::str::FromStr;
 use utils::parse::parse_time_range;
-use chrono::NaiveDateTime;
+use chrono::{ Duration, NaiveDateTime };
 
 pub struct TimeRange {
     pub from: NaiveDateTime,
@@ -19,3 +19,31 @@ impl FromStr for TimeRange {
         }
     }
 }
+
+/// Size of the buckets a time range is split into
+#[derive(Clone, Copy)]
+pub enum Granularity {
+    Minute,
+    Hour
+}
+
+impl Granularity {
+    pub fn duration(self) -> Duration {
+        match self {
+            Granularity::Minute => Duration::minutes(1),
+            Granularity::Hour => Duration::hours(1)
+        }
+    }
+}
+
+impl FromStr for Granularity {
+    type Err = ();
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        match data {
+            "minute" => Ok(Granularity::Minute),
+            "hour" => Ok(Granularity::Hour),
+            _ => Err(())
+        }
+    }
+}
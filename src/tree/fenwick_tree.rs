@@ -0,0 +1,73 @@
+use super::RangeAggregate;
+use monoid::Group;
+
+/// Fenwick tree (binary indexed tree): the node at index `i` aggregates the `i & -i` leaves
+/// ending at leaf `i`, so that the aggregate of the first leaves is found by appending O(log N)
+/// nodes and the one of a range by removing a prefix from another
+#[derive(Clone)]
+pub struct FenwickTree<T> {
+    nodes: Vec<T> // For efficiency and convenience we throw up the first emplacement of the vector
+}
+
+// Helpers for indexing
+macro_rules! index {
+    (next, $i: expr) => ($i + ($i & $i.wrapping_neg()));
+    (previous, $i: expr) => ($i - ($i & $i.wrapping_neg()));
+}
+
+impl<T: Copy + Group> FenwickTree<T> {
+    /// Create the tree from its leaves, in O(N) operations
+    pub fn with_leaves(leaves: &[T]) -> Self {
+        let mut nodes = Vec::with_capacity(leaves.len() + 1);
+        nodes.push(T::m_empty());
+        nodes.extend_from_slice(leaves);
+
+        // Add each node to the next one covering it
+        for i in 1 .. nodes.len() {
+            let next = index!(next, i);
+            if next < nodes.len() {
+                nodes[next] = nodes[next].m_append(&nodes[i]);
+            }
+        }
+
+        FenwickTree { nodes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Aggregate the first `end` leaves
+    fn prefix(&self, end: usize) -> T {
+        let mut acc = T::m_empty();
+        let mut i = end;
+        while i > 0 {
+            acc = acc.m_append(&self.nodes[i]);
+            i = index!(previous, i);
+        }
+        acc
+    }
+
+    /// Append `value` to the leaf at `index`, in O(log N) operations
+    pub fn add(&mut self, index: usize, value: T) {
+        let mut i = index + 1;
+        while i < self.nodes.len() {
+            self.nodes[i] = self.nodes[i].m_append(&value);
+            i = index!(next, i);
+        }
+    }
+}
+
+impl<T: Copy + Group> RangeAggregate<T> for FenwickTree<T> {
+    fn aggregate(&self, left: usize, right: usize) -> T {
+        if self.is_empty() || left > right {
+            return T::m_empty();
+        }
+        let right = ::std::cmp::min(right, self.len() - 1);
+        self.prefix(right + 1).m_remove(&self.prefix(left))
+    }
+}
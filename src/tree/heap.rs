@@ -1,5 +1,41 @@
-pub struct MinHeap<T> {
-    nodes: Vec<T>  
+use std::cmp::Ordering;
+
+/// Orders of the elements of a heap
+pub trait Compare<T> {
+    fn compare(&self, a: &T, b: &T) -> Ordering;
+}
+
+/// Natural order of `Ord` elements
+#[derive(Clone, Copy, Default)]
+pub struct Natural;
+
+impl<T: Ord> Compare<T> for Natural {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+/// Order of the keys given by a function
+#[derive(Clone, Copy)]
+pub struct ByKey<F>(pub F);
+
+impl<T, K: Ord, F: Fn(&T) -> K> Compare<T> for ByKey<F> {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        (self.0)(a).cmp(&(self.0)(b))
+    }
+}
+
+/// Order given by a comparator function
+impl<T, F: Fn(&T, &T) -> Ordering> Compare<T> for F {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        self(a, b)
+    }
+}
+
+/// Binary heap whose root is the smallest element according to `C`
+pub struct MinHeap<T, C = Natural> {
+    nodes: Vec<T>,
+    compare: C
 }
 
 // Helpers for indexing
@@ -10,17 +46,44 @@ macro_rules! index {
     (right, $i: expr) => (($i + 1) << 1);
 }
 
-impl<T: Copy + Ord> MinHeap<T> {
+impl<T: Ord> MinHeap<T> {
     pub fn new() -> Self {
+        MinHeap::with_compare(Natural)
+    }
+}
+
+impl<T, K: Ord, F: Fn(&T) -> K> MinHeap<T, ByKey<F>> {
+    /// Create a heap whose root has the smallest key
+    pub fn by_key(key: F) -> Self {
+        MinHeap::with_compare(ByKey(key))
+    }
+}
+
+impl<T, C: Compare<T>> MinHeap<T, C> {
+    pub fn with_compare(compare: C) -> Self {
         MinHeap {
-            nodes: Vec::new()
+            nodes: Vec::new(),
+            compare
+        }
+    }
+
+    /// Arrange the elements of a vector into a heap, in O(N) operations
+    pub fn from_vec(nodes: Vec<T>, compare: C) -> Self {
+        let mut heap = MinHeap { nodes, compare };
+        for from in (0 .. heap.nodes.len() >> 1).rev() {
+            heap.heapify_down(from);
         }
+        heap
     }
 
     pub fn len(&self) -> usize {
         self.nodes.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
     pub fn peek(&self) -> Option<&T> {
         self.nodes.get(index!(root))
     }
@@ -32,56 +95,103 @@ impl<T: Copy + Ord> MinHeap<T> {
     }
 
     pub fn extract(&mut self) -> Option<T> {
-        match self.len() {
-            0 => None,
-            1 => self.nodes.pop(),
-            _ => {
-                let element = self.nodes.get(index!(root))
-                                        .unwrap()
-                                        .clone();
-                let new_head = self.nodes.pop().unwrap();
-                self.nodes[index!(root)] = new_head;
-                self.heapify_down(index!(root));
-                Some(element)
-            }
+        let last = self.nodes.len().checked_sub(1)?;
+        self.nodes.swap(index!(root), last);
+        let element = self.nodes.pop();
+        self.heapify_down(index!(root));
+        element
+    }
+
+    /// Replace the root with `element` and return it, which is cheaper than extracting the root
+    /// then inserting
+    pub fn replace_root(&mut self, element: T) -> Option<T> {
+        if self.nodes.is_empty() {
+            self.nodes.push(element);
+            return None;
         }
+        let root = ::std::mem::replace(&mut self.nodes[index!(root)], element);
+        self.heapify_down(index!(root));
+        Some(root)
+    }
+
+    /// Elements in increasing order, in O(N log N) operations
+    pub fn into_sorted_vec(self) -> Vec<T> {
+        let mut elements = self.into_decreasing_vec();
+        elements.reverse();
+        elements
+    }
+
+    /// Elements in decreasing order: the root is moved after the heap shrinking at the front of
+    /// the vector, in O(N log N) operations
+    fn into_decreasing_vec(mut self) -> Vec<T> {
+        for end in (1 .. self.nodes.len()).rev() {
+            self.nodes.swap(index!(root), end);
+            self.sift_down(index!(root), end);
+        }
+        self.nodes
+    }
+
+    fn less(&self, a: usize, b: usize) -> bool {
+        self.compare.compare(&self.nodes[a], &self.nodes[b]) == Ordering::Less
     }
 
     fn heapify_down(&mut self, from: usize) {
         let length = self.nodes.len();
-        let left_index = index!(left, from);
-        let right_index = index!(right, from);
+        self.sift_down(from, length);
+    }
 
-        let mut min_index = from;
+    /// Move an element down until it is smaller than its children, among the first `length`
+    /// nodes
+    fn sift_down(&mut self, from: usize, length: usize) {
+        let mut from = from;
+        loop {
+            let left_index = index!(left, from);
+            let right_index = index!(right, from);
 
-        if left_index < length && self.nodes[left_index] < self.nodes[min_index] {
-            min_index = left_index;
-        }
+            let mut min_index = from;
 
-        if right_index < length && self.nodes[right_index] < self.nodes[min_index] {
-            min_index = right_index;
-        }
+            if left_index < length && self.less(left_index, min_index) {
+                min_index = left_index;
+            }
+
+            if right_index < length && self.less(right_index, min_index) {
+                min_index = right_index;
+            }
 
-        if from != min_index {
+            if from == min_index {
+                return;
+            }
             self.nodes.swap(from, min_index);
-            self.heapify_down(min_index);
+            from = min_index;
         }
     }
 
     fn heapify_up(&mut self, from: usize) {
-        if from == index!(root) {
-            return;
-        }
-
-        let parent = index!(parent, from);
-        if self.nodes[from] < self.nodes[parent] {
+        let mut from = from;
+        while from != index!(root) {
+            let parent = index!(parent, from);
+            if !self.less(from, parent) {
+                return;
+            }
             self.nodes.swap(from, parent);
-            self.heapify_up(parent);
+            from = parent;
         }
     }
 }
 
-impl<T> IntoIterator for MinHeap<T> {
+impl<T: Ord> Default for MinHeap<T> {
+    fn default() -> Self {
+        MinHeap::new()
+    }
+}
+
+impl<T: Ord> From<Vec<T>> for MinHeap<T> {
+    fn from(nodes: Vec<T>) -> Self {
+        MinHeap::from_vec(nodes, Natural)
+    }
+}
+
+impl<T, C> IntoIterator for MinHeap<T, C> {
     type Item = T;
     type IntoIter = ::std::vec::IntoIter<T>;
 
@@ -89,3 +199,56 @@ impl<T> IntoIterator for MinHeap<T> {
         self.nodes.into_iter()
     }
 }
+
+/// The `capacity` largest elements pushed, kept in a min-heap whose root is evicted by larger
+/// elements: O(N log K) operations and O(K) storage for N elements
+pub struct BoundedTopK<T, C = Natural> {
+    heap: MinHeap<T, C>,
+    capacity: usize
+}
+
+impl<T: Ord> BoundedTopK<T> {
+    pub fn new(capacity: usize) -> Self {
+        BoundedTopK::with_compare(capacity, Natural)
+    }
+}
+
+impl<T, C: Compare<T>> BoundedTopK<T, C> {
+    pub fn with_compare(capacity: usize, compare: C) -> Self {
+        BoundedTopK {
+            heap: MinHeap::with_compare(compare),
+            capacity
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    /// Keep `element` if it is among the largest ones, returning the element evicted (or
+    /// `element` itself when it is not kept)
+    pub fn push(&mut self, element: T) -> Option<T> {
+        if self.heap.len() < self.capacity {
+            self.heap.insert(element);
+            return None;
+        }
+        let evicted = match self.heap.peek() {
+            Some(smallest) => self.heap.compare.compare(smallest, &element) == Ordering::Less,
+            None => false
+        };
+        if evicted {
+            self.heap.replace_root(element)
+        } else {
+            Some(element)
+        }
+    }
+
+    /// Elements kept, largest first
+    pub fn into_sorted_vec(self) -> Vec<T> {
+        self.heap.into_decreasing_vec()
+    }
+}
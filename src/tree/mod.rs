@@ -14,6 +14,15 @@ pub trait GenericTree<T: Copy> {
     fn root(&self) -> Option<T>;
 }
 
+/// Structures answering the aggregate of any range of their leaves
+pub trait RangeAggregate<T> {
+    /// Aggregate the leaves `left`-`right`, both included
+    fn aggregate(&self, left: usize, right: usize) -> T;
+}
+
 pub mod range_tree;
 pub mod segment_tree;
+pub mod fenwick_tree;
+pub mod sparse_table;
 pub mod heap;
+pub mod trie;
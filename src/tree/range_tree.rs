@@ -1,159 +1,101 @@
-use std::boxed::Box;
 use super::GenericTree;
 
+/// Sorted leaves laid out in breadth-first order (Eytzinger layout): the children of the node at
+/// index `i` are at `2i` and `2i + 1`, so that a search reads the nodes of the first levels from
+/// the same cache lines instead of following pointers.
 #[derive(Clone)]
 pub struct RangeTree<T> {
-    root: Option<Box<Node<T>>>
+    nodes: Vec<T>,      // For efficiency and convenience we throw up the first emplacement of the vector
+    ranks: Vec<usize>   // Index of the leaf stored in each node, in the sorted leaves
 }
 
-#[derive(Clone)]
-struct Node<T> {
-    value: T,
-    left: RangeTree<T>,
-    right: RangeTree<T>
+// Helpers for indexing
+macro_rules! index {
+    (root) => (1);
+    (left, $i: expr) => ($i << 1);
+    (right, $i: expr) => (($i << 1) | 1);
 }
 
 impl<T: Copy> GenericTree<T> for RangeTree<T> {
     fn nil() -> Self {
-        RangeTree { root: None }
+        RangeTree {
+            nodes: Vec::new(),
+            ranks: Vec::new()
+        }
     }
 
     fn with_root(root: T) -> Self {
-        RangeTree {
-            root: Some(Box::new(Node {
-                value: root,
-                left: RangeTree::nil(),
-                right: RangeTree:: nil()
-            }))
-        }
+        RangeTree::with_leaves(&[root])
     }
 
     /// Create the range tree given a sorted array of leaves.
     fn with_leaves(leaves: &[T]) -> Self {
-        match leaves.len() {
-            0 => RangeTree::nil(),
-            1 => RangeTree::with_root(leaves[0]),
-            length => {
-                let (left_leaves, right_leaves) = leaves.split_at(length >> 1);
-                let left_tree = RangeTree::with_leaves(left_leaves);
-                let right_tree = RangeTree::with_leaves(right_leaves);
-                RangeTree {
-                    root: Some(Box::new(Node {
-                        value: *left_leaves.last().unwrap(),
-                        left: left_tree,
-                        right: right_tree
-                    }))
-                }
-            }
-        }
+        let first = match leaves.first() {
+            Some(first) => *first,
+            None => return RangeTree::nil()
+        };
+        let mut tree = RangeTree {
+            nodes: vec![first; leaves.len() + 1],
+            ranks: vec![0; leaves.len() + 1]
+        };
+        tree.place(leaves, &mut 0, index!(root));
+        tree
     }
 
     fn root(&self) -> Option<T> {
-        match self.root {
-            None => None,
-            Some(ref boxed_node) => Some(boxed_node.value)
-        }
+        self.nodes.get(index!(root)).cloned()
     }
 }
 
-impl<T: Copy + Ord> RangeTree<T> {
-    /// Find the first sub-tree in which the given range fits.
-    /// Requirement: `from <= to`
-    fn find_split_node(&self, from: &T, to: &T) -> Option<&Self> {
-        match self.root {
-            None => None,
-
-            // [ ... from <= to <= root ... ]: explore left sub-tree
-            Some(ref boxed_node) if to <= &boxed_node.value => {
-                let maybe_left = boxed_node.left.find_split_node(from, to);
-                if maybe_left.is_none() {
-                    return Some(self);
-                }
-                maybe_left
-            },
-
-            // [ ... root < from <= to ... ]: explore right sub-tree
-            Some(ref boxed_node) if from > &boxed_node.value => {
-                let maybe_right = boxed_node.right.find_split_node(from, to);
-                if maybe_right.is_none() {
-                    return Some(self);
-                }
-                maybe_right
-            },
-
-            // [ ... from <= root < to ...]: split node found
-            Some(_) => {
-                Some(self)
-            }
-        }
+impl<T: Copy> RangeTree<T> {
+    fn len(&self) -> usize {
+        self.nodes.len().saturating_sub(1)
     }
 
-    /// Find the smallest `bound >= from`
-    fn left_bound(&self, from: &T) -> Option<T> {
-        match self.root {
-            None => None,
-
-            Some(ref boxed_node) if &boxed_node.value >= from => {
-                let maybe_left = boxed_node.left.left_bound(from);
-                if maybe_left.is_none() {
-                    return Some(boxed_node.value);
-                }
-                maybe_left
-            },
-
-            Some(ref boxed_node) => {
-                let maybe_left = boxed_node.right.left_bound(from);
-                if maybe_left.is_none() {
-                    if &boxed_node.value < from {
-                        return None;
-                    }
-                    return Some(boxed_node.value);
-                }
-                maybe_left
-            }
+    /// Place the leaves in the sub-tree rooted at `node` by an in-order traversal, `rank` being
+    /// the next leaf to place
+    fn place(&mut self, leaves: &[T], rank: &mut usize, node: usize) {
+        if node > leaves.len() {
+            return;
         }
+        self.place(leaves, rank, index!(left, node));
+        self.nodes[node] = leaves[*rank];
+        self.ranks[node] = *rank;
+        *rank += 1;
+        self.place(leaves, rank, index!(right, node));
     }
+}
 
-    /// Find the largest bound `bound <= to`
-    fn right_bound(&self, to: &T) -> Option<T> {
-        match self.root {
-            None => None,
-
-            Some(ref boxed_node) if &boxed_node.value < to => {
-                let maybe_right = boxed_node.right.right_bound(to);
-                if maybe_right.is_none() {
-                    return Some(boxed_node.value);
-                }
-                maybe_right
-            },
+impl<T: Copy + Ord> RangeTree<T> {
+    /// Count the leaves `< bound`, or `<= bound` when `inclusive`, in O(log N) operations
+    fn count_below(&self, bound: &T, inclusive: bool) -> usize {
+        let length = self.len();
+        let mut node = index!(root);
+        while node <= length {
+            let below = if inclusive { self.nodes[node] <= *bound } else { self.nodes[node] < *bound };
+            node = if below { index!(right, node) } else { index!(left, node) };
+        }
 
-            Some(ref boxed_node) => {
-                let maybe_right = boxed_node.left.right_bound(to);
-                if maybe_right.is_none() {
-                    if &boxed_node.value > to {
-                        return None;
-                    }
-                    return Some(boxed_node.value);
-                }
-                maybe_right
-            }
+        // The search went right below the last node it went left from, which holds the first
+        // leaf that is not below the bound: drop the trailing right turns and the left turn
+        node >>= (!node).trailing_zeros() + 1;
+        match node {
+            0 => length,
+            node => self.ranks[node]
         }
     }
 
-    /// Find the largest range included in the given range [from ; to[
-    pub fn largest_range(&self, from: &T, to: &T) -> Option<(T, T)> {
+    /// Find the indexes of the first and last leaves of the range [from ; to], if it contains any
+    pub fn largest_range(&self, from: &T, to: &T) -> Option<(usize, usize)> {
         if from > to {
             return None;
         }
-        self.find_split_node(from, to)
-            .map(|tree| {
-                let maybe_left = tree.left_bound(from);
-                let maybe_right = tree.right_bound(to);
-                match (maybe_left, maybe_right) {
-                    (Some(left), Some(right)) => Some((left, right)),
-                    _ => None
-                }
-            })
-            .unwrap_or(None)
+        let first = self.count_below(from, false);
+        let end = self.count_below(to, true);
+        if first < end {
+            Some((first, end - 1))
+        } else {
+            None
+        }
     }
 }
@@ -1,76 +1,241 @@
-use super::GenericTree;
+use super::{ GenericTree, RangeAggregate };
 use monoid::Monoid;
 
+/// Updates applied lazily to ranges of leaves. `a.m_append(&b)` is the action applying `a` then
+/// `b`, and acting on the aggregate of a range must give the aggregate of the leaves acted upon.
+pub trait Action<T>: Monoid + Copy {
+    /// Apply the action to `value`, the aggregate of `length` leaves
+    fn act(&self, value: &T, length: usize) -> T;
+}
+
+/// No updates besides the ones of single leaves
+impl<T: Copy> Action<T> for () {
+    fn act(&self, value: &T, _: usize) -> T {
+        *value
+    }
+}
+
+/// Segment tree over a power of two of leaves, the ones after the first `length` being neutral
 #[derive(Clone)]
-pub struct SegmentTree<T> {
-    nodes: Vec<T> // For efficiency and convenience we throw up the first emplacement of the vector
+pub struct SegmentTree<T, A = ()> {
+    nodes: Vec<T>,      // For efficiency and convenience we throw up the first emplacement of the vector
+    actions: Vec<A>,    // Actions not yet applied to the children of internal nodes
+    length: usize,      // Number of leaves
+    lazy: bool          // Whether actions may be pending, queries then go down from the root
 }
 
 // Helpers for indexing
 macro_rules! index {
+    (root) => (1);
     (left, $i: expr) => ($i << 1);
     (right, $i: expr) => (($i << 1) | 1);
     (parent, $i: expr) => ($i >> 1);
 }
 
-impl<T: Copy + Monoid> GenericTree<T> for SegmentTree<T> {
+impl<T: Copy + Monoid, A: Action<T>> GenericTree<T> for SegmentTree<T, A> {
     fn nil() -> Self {
-        unimplemented!();
+        SegmentTree {
+            nodes: Vec::new(),
+            actions: Vec::new(),
+            length: 0,
+            lazy: false
+        }
     }
 
-    fn with_root(_: T) -> Self {
-        unimplemented!();
+    fn with_root(root: T) -> Self {
+        SegmentTree::with_leaves(&[root])
     }
 
     fn with_leaves(leaves: &[T]) -> Self {
         let length = leaves.len();
-        let mut v = Vec::with_capacity(length << 1);
+        let capacity = match length {
+            0 => 0,
+            length => length.next_power_of_two()
+        };
+        let mut v = Vec::with_capacity(capacity << 1);
 
-        // Pre-fill the vector with neutral element and append the leaves
-        for _ in 0 .. length {
+        // Pre-fill the vector with neutral element, append the leaves and pad them
+        for _ in 0 .. capacity {
             v.push(T::m_empty());
         }
         v.extend_from_slice(leaves);
+        for _ in length .. capacity {
+            v.push(T::m_empty());
+        }
 
         // Compute internal nodes all the way up
-        for i in (1 .. length).rev() {
+        for i in (1 .. capacity).rev() {
             let left = v[index!(left, i)];
             let right = v[index!(right, i)];
             v[i] = left.m_append(&right);
         }
 
-        SegmentTree { nodes: v }
+        SegmentTree {
+            nodes: v,
+            actions: vec![A::m_empty(); capacity],
+            length,
+            lazy: false
+        }
     }
 
     fn root(&self) -> Option<T> {
-        match self.nodes.len() {
-            0 | 1 => None,
-            _ => Some(self.nodes[1])
+        match self.length {
+            0 => None,
+            _ => Some(self.nodes[index!(root)])
         }
     }
 }
 
-impl<T: Copy + Monoid> SegmentTree<T> {
+impl<T: Copy + Monoid, A: Action<T>> SegmentTree<T, A> {
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    fn capacity(&self) -> usize {
+        self.nodes.len() >> 1
+    }
+
+    /// Number of leaves below `node`
+    fn node_length(&self, node: usize) -> usize {
+        self.capacity() >> node.ilog2()
+    }
+
     /// Query the segment tree in the range `left`-`right`
     pub fn query(&self, left: usize, right: usize) -> T {
-        let half = self.nodes.len() >> 1;
-        let mut left = half + left;
-        let mut right = half + right;
-        let mut acc = T::m_empty();
+        if self.is_empty() || left > right {
+            return T::m_empty();
+        }
+        let right = ::std::cmp::min(right, self.length - 1);
+        if self.lazy {
+            return self.query_node(index!(root), 0, self.capacity() - 1, left, right);
+        }
 
-        while left <= right {
+        // Move both bounds up, appending the nodes left of the range to the left accumulator and
+        // the ones right of it in front of the right accumulator
+        let mut left = self.capacity() + left;
+        let mut right = self.capacity() + right + 1;
+        let mut left_acc = T::m_empty();
+        let mut right_acc = T::m_empty();
+        while left < right {
             if left & 1 == 1 {
-                acc = acc.m_append(&self.nodes[left]);
+                left_acc = left_acc.m_append(&self.nodes[left]);
                 left += 1;
             }
-            if right & 1 == 0 {
-                acc = acc.m_append(&self.nodes[right]);
+            if right & 1 == 1 {
                 right -= 1;
+                right_acc = self.nodes[right].m_append(&right_acc);
             }
-            left = index!(parent, left);
-            right = index!(parent, right);
+            left >>= 1;
+            right >>= 1;
+        }
+        left_acc.m_append(&right_acc)
+    }
+
+    /// Aggregate the leaves `left`-`right` below `node`, which spans the leaves `from`-`to`. The
+    /// actions pending on the way down are applied on the way up.
+    fn query_node(&self, node: usize, from: usize, to: usize, left: usize, right: usize) -> T {
+        if right < from || to < left {
+            return T::m_empty();
+        }
+        if left <= from && to <= right {
+            return self.nodes[node];
+        }
+
+        let middle = (from + to) >> 1;
+        let value = self.query_node(index!(left, node), from, middle, left, right)
+                        .m_append(&self.query_node(index!(right, node), middle + 1, to, left, right));
+        let covered = ::std::cmp::min(to, right) - ::std::cmp::max(from, left) + 1;
+        self.actions[node].act(&value, covered)
+    }
+
+    /// Replace the leaf at `index`, in O(log N) operations
+    pub fn update(&mut self, index: usize, value: T) {
+        assert!(index < self.length, "index {} out of a segment tree of {} leaves", index, self.length);
+
+        // Apply the actions pending on the path to the leaf
+        let capacity = self.capacity();
+        let leaf = capacity + index;
+        for depth in (1 .. capacity.trailing_zeros() + 1).rev() {
+            self.push_down(leaf >> depth);
         }
 
-        acc
+        self.nodes[leaf] = value;
+        let mut node = index!(parent, leaf);
+        while node >= index!(root) {
+            self.nodes[node] = self.nodes[index!(left, node)].m_append(&self.nodes[index!(right, node)]);
+            // Move up to the parent
+            node >>= 1;
+        }
+    }
+
+    /// Apply `action` to the leaves `left`-`right`, in O(log N) operations
+    pub fn update_range(&mut self, left: usize, right: usize, action: A) {
+        if self.is_empty() || left > right {
+            return;
+        }
+        let right = ::std::cmp::min(right, self.length - 1);
+        let to = self.capacity() - 1;
+        self.lazy = true;
+        self.update_node(index!(root), 0, to, left, right, action);
+    }
+
+    fn update_node(&mut self, node: usize, from: usize, to: usize, left: usize, right: usize, action: A) {
+        if right < from || to < left {
+            return;
+        }
+        if left <= from && to <= right {
+            self.apply(node, action);
+            return;
+        }
+
+        self.push_down(node);
+        let middle = (from + to) >> 1;
+        self.update_node(index!(left, node), from, middle, left, right, action);
+        self.update_node(index!(right, node), middle + 1, to, left, right, action);
+        self.nodes[node] = self.nodes[index!(left, node)].m_append(&self.nodes[index!(right, node)]);
+    }
+
+    /// Apply `action` to the sub-tree of `node`, lazily for its children
+    fn apply(&mut self, node: usize, action: A) {
+        self.nodes[node] = action.act(&self.nodes[node], self.node_length(node));
+        if node < self.capacity() {
+            self.actions[node] = self.actions[node].m_append(&action);
+        }
+    }
+
+    /// Hand the action pending on an internal node down to its children
+    fn push_down(&mut self, node: usize) {
+        let action = ::std::mem::replace(&mut self.actions[node], A::m_empty());
+        self.apply(index!(left, node), action);
+        self.apply(index!(right, node), action);
+    }
+
+    /// Append a leaf, the tree doubling its capacity when it is full: O(log N) operations
+    /// amortized
+    pub fn push(&mut self, value: T) {
+        if self.length < self.capacity() {
+            self.length += 1;
+            let index = self.length - 1;
+            self.update(index, value);
+            return;
+        }
+
+        for node in 1 .. self.capacity() {
+            self.push_down(node);
+        }
+        let capacity = self.capacity();
+        let mut leaves = self.nodes[capacity .. capacity + self.length].to_vec();
+        leaves.push(value);
+        *self = SegmentTree::with_leaves(&leaves);
+    }
+}
+
+impl<T: Copy + Monoid, A: Action<T>> RangeAggregate<T> for SegmentTree<T, A> {
+    fn aggregate(&self, left: usize, right: usize) -> T {
+        self.query(left, right)
     }
 }
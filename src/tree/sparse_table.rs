@@ -0,0 +1,49 @@
+use super::RangeAggregate;
+use monoid::Idempotent;
+
+/// Sparse table: level `k` aggregates the `2^k` leaves starting at each leaf, so that any range
+/// is covered by two overlapping rows of a level, requiring O(N log N) storage
+#[derive(Clone)]
+pub struct SparseTable<T> {
+    levels: Vec<Vec<T>>
+}
+
+impl<T: Copy + Idempotent> SparseTable<T> {
+    /// Create the table from its leaves, in O(N log N) operations
+    pub fn with_leaves(leaves: &[T]) -> Self {
+        let mut levels = vec![leaves.to_vec()];
+        let mut width = 1;
+        while width << 1 <= leaves.len() {
+            let level: Vec<T> = {
+                let previous = &levels[levels.len() - 1];
+                (0 .. previous.len() - width).map(|i| previous[i].m_append(&previous[i + width]))
+                                              .collect()
+            };
+            levels.push(level);
+            width <<= 1;
+        }
+
+        SparseTable { levels }
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: Copy + Idempotent> RangeAggregate<T> for SparseTable<T> {
+    /// Aggregate two overlapping rows in O(1) operations
+    fn aggregate(&self, left: usize, right: usize) -> T {
+        if self.is_empty() || left > right {
+            return T::m_empty();
+        }
+        let right = ::std::cmp::min(right, self.len() - 1);
+        let level = (right + 1 - left).ilog2() as usize;
+        let rows = &self.levels[level];
+        rows[left].m_append(&rows[right + 1 - (1 << level)])
+    }
+}
@@ -0,0 +1,160 @@
+use std::cmp::Reverse;
+
+use super::heap::MinHeap;
+
+/// Radix tree over strings: edges are labelled by the bytes the keys of a sub-tree share, so
+/// that there are at most two nodes per key. Each node knows the total weight of its keys and
+/// the largest one, for finding the heaviest completions of a prefix first.
+pub struct Trie<T> {
+    nodes: Vec<Node<T>>
+}
+
+struct Node<T> {
+    label: Box<[u8]>,               // Bytes from the parent to this node
+    children: Vec<usize>,           // Sorted by the first byte of their label
+    value: Option<(T, usize)>,      // Value and weight of the key ending at this node
+    popularity: usize,              // Total weight of the keys of the sub-tree
+    best: usize                     // Largest weight of a key of the sub-tree
+}
+
+// Helpers for indexing
+macro_rules! index {
+    (root) => (0);
+}
+
+/// Items of the best-first search of completions: sub-trees are ordered by the weight of their
+/// best key, which is greater than or equal to the weight of any key they contain
+enum Candidate {
+    Node(usize),
+    Key(usize)
+}
+
+impl<T: Copy> Trie<T> {
+    /// Create the trie of keys given with their value and weight, in O(L log N) operations for
+    /// keys of L bytes in total
+    pub fn with_entries(mut entries: Vec<(&str, T, usize)>) -> Self {
+        entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        let mut trie = Trie { nodes: Vec::new() };
+        trie.build(&entries, 0, Box::new([]));
+        trie
+    }
+
+    /// Build the sub-tree of sorted entries sharing their first `depth` bytes
+    fn build(&mut self, entries: &[(&str, T, usize)], depth: usize, label: Box<[u8]>) -> usize {
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            label,
+            children: Vec::new(),
+            value: None,
+            popularity: 0,
+            best: 0
+        });
+
+        // Sorted keys: the ones ending here, if any, come first and the first one is kept
+        let ending = entries.iter().take_while(|entry| entry.0.len() == depth).count();
+        if let Some(&(_, value, weight)) = entries[.. ending].first() {
+            self.nodes[node].value = Some((value, weight));
+        }
+        let mut rest = &entries[ending ..];
+
+        // Group the other keys by their next byte, each group being a child labelled by the bytes
+        // its keys share: the ones its first and last keys share since they are sorted
+        let mut children = Vec::new();
+        while let Some(first) = rest.first() {
+            let byte = first.0.as_bytes()[depth];
+            let length = rest.iter()
+                             .position(|entry| entry.0.as_bytes()[depth] != byte)
+                             .unwrap_or(rest.len());
+            let (group, next) = rest.split_at(length);
+            let (first, last) = (group[0].0.as_bytes(), group[length - 1].0.as_bytes());
+            let shared = depth + first[depth ..].iter()
+                                                .zip(&last[depth ..])
+                                                .take_while(|(a, b)| a == b)
+                                                .count();
+            let label = first[depth .. shared].to_vec().into_boxed_slice();
+            children.push(self.build(group, shared, label));
+            rest = next;
+        }
+
+        let own = self.nodes[node].value.map_or(0, |(_, weight)| weight);
+        let popularity = children.iter().fold(own, |total, &child| total + self.nodes[child].popularity);
+        let best = children.iter().fold(own, |best, &child| ::std::cmp::max(best, self.nodes[child].best));
+        let node_ref = &mut self.nodes[node];
+        node_ref.children = children;
+        node_ref.popularity = popularity;
+        node_ref.best = best;
+        node
+    }
+
+    /// Find the sub-tree of the keys starting with `prefix`, in O(P) operations for a prefix of P
+    /// bytes
+    fn find(&self, prefix: &str) -> Option<usize> {
+        let prefix = prefix.as_bytes();
+        let mut node = index!(root);
+        let mut depth = 0;
+        while depth < prefix.len() {
+            let rest = &prefix[depth ..];
+            let child = *self.nodes[node].children
+                                         .iter()
+                                         .find(|&&child| self.nodes[child].label[0] == rest[0])?;
+            let label = &self.nodes[child].label;
+            if rest.len() <= label.len() {
+                // The prefix ends within the label
+                return if label.starts_with(rest) { Some(child) } else { None };
+            }
+            if !rest.starts_with(label) {
+                return None;
+            }
+            depth += label.len();
+            node = child;
+        }
+        Some(node)
+    }
+
+    /// Total weight of the keys starting with `prefix`
+    pub fn popularity(&self, prefix: &str) -> usize {
+        self.find(prefix).map_or(0, |node| self.nodes[node].popularity)
+    }
+
+    /// Values of the keys starting with `prefix`
+    pub fn values(&self, prefix: &str) -> Vec<T> {
+        let mut values = Vec::new();
+        let mut pending: Vec<usize> = self.find(prefix).into_iter().collect();
+        while let Some(node) = pending.pop() {
+            values.extend(self.nodes[node].value.map(|(value, _)| value));
+            pending.extend(&self.nodes[node].children);
+        }
+        values
+    }
+
+    /// The `k` heaviest keys starting with `prefix` with their weight, heaviest first. Sub-trees
+    /// are explored best-first so that only the nodes leading to them and their siblings are
+    /// visited.
+    pub fn completions(&self, prefix: &str, k: usize) -> Vec<(T, usize)> {
+        let mut completions = Vec::with_capacity(k);
+        let weight = |candidate: &Candidate| match *candidate {
+            Candidate::Node(node) => self.nodes[node].best,
+            Candidate::Key(node) => self.nodes[node].value.map_or(0, |(_, weight)| weight)
+        };
+        let mut candidates = MinHeap::by_key(|candidate: &Candidate| Reverse(weight(candidate)));
+        if let Some(node) = self.find(prefix) {
+            candidates.insert(Candidate::Node(node));
+        }
+
+        while completions.len() < k {
+            match candidates.extract() {
+                Some(Candidate::Key(node)) => completions.extend(self.nodes[node].value),
+                Some(Candidate::Node(node)) => {
+                    if self.nodes[node].value.is_some() {
+                        candidates.insert(Candidate::Key(node));
+                    }
+                    for &child in &self.nodes[node].children {
+                        candidates.insert(Candidate::Node(child));
+                    }
+                },
+                None => break
+            }
+        }
+        completions
+    }
+}
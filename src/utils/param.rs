@@ -0,0 +1,87 @@
+use std::str::FromStr;
+
+use problem::Problem;
+use solver::TrendScore;
+use time_range::{ Granularity, TimeRange };
+use format::Format;
+
+/// A captured parameter that is always matched by the router. Decoding is deferred to the
+/// handlers so that they can tell which parameter is invalid instead of answering not found.
+pub struct Param<T> {
+    raw: String,
+    value: Option<T>
+}
+
+impl<T: FromStr> FromStr for Param<T> {
+    type Err = ();
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        Ok(Param {
+            raw: data.to_string(),
+            value: data.parse().ok()
+        })
+    }
+}
+
+impl<T: Expected> Param<T> {
+    /// Get the decoded value of a required parameter
+    pub fn get(self, name: &'static str) -> Result<T, Problem> {
+        let raw = self.raw;
+        self.value.ok_or_else(|| Problem::invalid_param(name, &raw, format!("expected {}", T::expected())))
+    }
+
+    /// Get the decoded value of an optional parameter
+    pub fn get_optional(maybe_param: Option<Self>, name: &'static str) -> Result<Option<T>, Problem> {
+        match maybe_param {
+            Some(param) => param.get(name).map(Some),
+            None => Ok(None)
+        }
+    }
+}
+
+/// Describe the values accepted for a parameter
+pub trait Expected {
+    fn expected() -> &'static str;
+}
+
+impl Expected for u32 {
+    fn expected() -> &'static str {
+        "a 32 bits unsigned integer"
+    }
+}
+
+impl Expected for usize {
+    fn expected() -> &'static str {
+        "an unsigned integer"
+    }
+}
+
+impl Expected for f64 {
+    fn expected() -> &'static str {
+        "a number"
+    }
+}
+
+impl Expected for TimeRange {
+    fn expected() -> &'static str {
+        "a time range YYYY[-MM[-DD[ hh[:mm]]]]"
+    }
+}
+
+impl Expected for TrendScore {
+    fn expected() -> &'static str {
+        "absolute or relative"
+    }
+}
+
+impl Expected for Granularity {
+    fn expected() -> &'static str {
+        "minute or hour"
+    }
+}
+
+impl Expected for Format {
+    fn expected() -> &'static str {
+        "json, csv, tsv or ndjson"
+    }
+}
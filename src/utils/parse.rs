@@ -13,14 +13,14 @@ use chrono::Duration;
 // or the string in case of a failure / non consumption
 type ParseResult<'a, T> = Result<(T, &'a str), &'a str>;
 
-fn parse_char(c: char, input: &str) -> ParseResult<()> {
+fn parse_char(c: char, input: &str) -> ParseResult<'_, ()> {
     match input.chars().next() {
         Some(e) if c == e =>  Ok(((), &input[1..])),
         _ => Err(input)
     }
 }
 
-fn parse_number<T: FromStr>(input: &str) -> ParseResult<T> {
+fn parse_number<T: FromStr>(input: &str) -> ParseResult<'_, T> {
     const RADIX: u32 = 10;
     let digits: String = input.chars().take_while(|c| c.is_digit(RADIX)).collect();
     let maybe_n = digits.parse::<T>();
@@ -105,9 +105,7 @@ pub fn parse_time_range(input: &str) -> Option<(NaiveDateTime, NaiveDateTime)> {
 
     // Return the range iff both bounds are valid dates
     maybe_from.and_then(|from| {
-        maybe_to.and_then(|to| {
-            Some((from, to))
-        })
+        maybe_to.map(|to| (from, to))
     })
 }
 